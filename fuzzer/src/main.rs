@@ -1,9 +1,11 @@
 use uavcan_llr::assembler::Assembler;
 use uavcan_llr::slicer::Slicer;
-use uavcan_llr::types::{TransferId, CanId, NodeId, SubjectId, Priority};
+use uavcan_llr::fugit::TimerInstantU32;
+use uavcan_llr::types::{TransferId, CanId, NodeId, SubjectId, Priority, PortId};
 
 fn main() {
-    let mut assembler = Assembler::<8, 7, 32, 32, 10>::new();
+    let mut assembler = Assembler::<8, 7, 32, 32, 32, 4, 1000, 10>::new();
+    assembler.subscribe(PortId::Subject(SubjectId::new(8).unwrap()), 512).unwrap();
     let payload = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13];
     let slicer = Slicer::<8, 7>::new(&payload, TransferId::new(31).unwrap()).frames_owned();
     let id0 = CanId::new_message_kind(
@@ -14,7 +16,7 @@ fn main() {
     );
     for frame in slicer {
         println!("p: {}", frame.len());
-        assembler.process_frame(id0, &frame, 0);
+        assembler.process_frame(id0, &frame, TimerInstantU32::from_ticks(0)).ok();
         println!("{}", assembler);
     }
 }
@@ -1,24 +1,71 @@
-use crate::tailbyte::{TailByte};
-use crate::types::{CanId, NodeId, TransferKind, Priority};
+use crate::tailbyte::{TailByte, Kind};
+use crate::dlc::is_valid_frame_len;
+use crate::types::{CanId, NodeId, TransferKind, Priority, PortId, TransferId};
+use crate::Error;
 use core::fmt::{Formatter, Display, Result as FmtResult};
-use heapless::FnvIndexMap;
+use heapless::{FnvIndexMap, Vec};
 use super::storage::{PiecesStorage};
-use super::transfer::{PayloadKind, Transfer, TransfersMapKey, TransferMachineOutput};
+use super::transfer::{Transfer, TransfersMapKey, TransferMachineOutput, QueuedTransfer, elapsed};
+use fugit::{TimerInstantU32, TimerDurationU32};
 use super::types::*;
 use crate::assembler::transfer::State;
 
+/// Transfer with the same ID from the same session is considered a duplicate if received earlier than that
+pub const DEFAULT_TRANSFER_ID_TIMEOUT_MS: u32 = 2000;
+
+/// Sessions idle for that many timer ticks are removed, before their age can no longer be measured
+const AGE_OUT_IDLE_TICKS: u32 = u32::MAX / 4;
+/// Sessions are checked for AGE_OUT_IDLE_TICKS that often
+const AGE_OUT_CHECK_TICKS: u32 = u32::MAX / 8;
+
+/// Reassembles transfers from incoming frames.
+/// MTU is the largest frame length accepted, actual MTU is detected for each transfer separately,
+/// so Classic CAN and CAN FD nodes can share the bus (use MTU = 64 for that).
+/// MAX_TRANSFERS bounds the amount of sessions (source, transfer kind) tracked at the same time,
+/// completed transfers are moved into a separate queue of MAX_READY items until popped.
+/// Only frames of the ports subscribed to (up to MAX_SUBSCRIPTIONS) are accepted.
+/// Time is measured by a wrapping timer running at TIMER_HZ, transfers that didn't receive any frames for
+/// TRANSFER_LIFETIME_MS are dropped when storage space is needed.
+/// Intervals longer than half of the timer range can't be measured, so sessions idle for a quarter of it
+/// are removed. For that process_frame() or expire() must be called at least once per eighth of the
+/// timer range (about 9 minutes at 1MHz), otherwise a long idle session may be mistaken for a recent one.
 pub struct Assembler<
     const MTU: usize,
     const MTU_M1: usize,
     const MAX_PIECES: usize,
     const MAX_TRANSFERS: usize,
-    const TRANSFER_LIFETIME: u32,
+    const MAX_READY: usize,
+    const MAX_SUBSCRIPTIONS: usize,
+    const TIMER_HZ: u32,
+    const TRANSFER_LIFETIME_MS: u32,
 >
 {
     pub(crate) transfers: FnvIndexMap<TransfersMapKey, Transfer<MTU>, MAX_TRANSFERS>,
+    pub(crate) ready: Vec<QueuedTransfer, MAX_READY>,
+    pub(crate) subscriptions: Vec<Subscription, MAX_SUBSCRIPTIONS>,
     pub(crate) storage: PiecesStorage<MTU_M1, MAX_PIECES>,
     pub(crate) latest_sequence_number: TransferSeq,
     pub(crate) counters: Counters,
+    pub(crate) config: Config,
+    /// Last time sessions were checked for AGE_OUT_IDLE_TICKS
+    pub(crate) last_age_out: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Config {
+    /// Timer ticks
+    pub(crate) transfer_id_timeout: u32,
+    pub(crate) ignore_duplicate_frames: bool,
+    pub(crate) local_node_id: Option<NodeId>,
+    pub(crate) promiscuous: bool,
+    /// Timer ticks, completed transfers are never expired if None
+    pub(crate) ready_transfer_timeout: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Subscription {
+    pub(crate) port_id: PortId,
+    pub(crate) extent: usize,
 }
 
 impl<
@@ -26,249 +73,767 @@ impl<
     const MTU_M1: usize,
     const MAX_PIECES: usize,
     const MAX_TRANSFERS: usize,
-    const TRANSFER_LIFETIME: u32,
-> Assembler<MTU, MTU_M1, MAX_PIECES, MAX_TRANSFERS, TRANSFER_LIFETIME>
+    const MAX_READY: usize,
+    const MAX_SUBSCRIPTIONS: usize,
+    const TIMER_HZ: u32,
+    const TRANSFER_LIFETIME_MS: u32,
+> Assembler<MTU, MTU_M1, MAX_PIECES, MAX_TRANSFERS, MAX_READY, MAX_SUBSCRIPTIONS, TIMER_HZ, TRANSFER_LIFETIME_MS>
 {
     pub fn new() -> Self {
         Assembler {
             transfers: FnvIndexMap::new(),
+            ready: Vec::new(),
+            subscriptions: Vec::new(),
             storage: PiecesStorage::new(),
             latest_sequence_number: 0,
             counters: Counters::default(),
+            config: Config {
+                transfer_id_timeout: TimerDurationU32::<TIMER_HZ>::millis(DEFAULT_TRANSFER_ID_TIMEOUT_MS).ticks(),
+                ignore_duplicate_frames: false,
+                local_node_id: None,
+                promiscuous: false,
+                ready_transfer_timeout: None,
+            },
+            last_age_out: 0,
         }
     }
 
-    pub fn process_frame(&mut self, id: CanId, payload: &[u8], time_now: u32) {
+    /// Set transfer-ID timeout (2s by default).
+    /// Transfers with the same ID from the same session received within that time are ignored.
+    /// Must be shorter than a quarter of the timer range.
+    pub fn set_transfer_id_timeout(&mut self, timeout: TimerDurationU32<TIMER_HZ>) {
+        self.config.transfer_id_timeout = timeout.ticks();
+    }
+
+    /// Ignore exact copies of the previously received frame (same CAN ID and data) instead of failing
+    /// the transfer. Some controllers retransmit a frame after a lost ACK. Disabled by default.
+    pub fn set_ignore_duplicate_frames(&mut self, enabled: bool) {
+        self.config.ignore_duplicate_frames = enabled;
+    }
+
+    /// Completed transfers that were not popped for that long are removed by expire(), disabled by default.
+    pub fn set_ready_transfer_timeout(&mut self, timeout: Option<TimerDurationU32<TIMER_HZ>>) {
+        self.config.ready_transfer_timeout = timeout.map(|t| t.ticks());
+    }
+
+    /// Node ID of this node, only service transfers addressed to it are accepted.
+    /// Anonymous node (None by default) can't receive service transfers.
+    pub fn set_local_node_id(&mut self, node_id: Option<NodeId>) {
+        self.config.local_node_id = node_id;
+    }
+
+    /// Accept service transfers addressed to any node, useful for bus monitors. Disabled by default.
+    pub fn set_promiscuous(&mut self, enabled: bool) {
+        self.config.promiscuous = enabled;
+    }
+
+    /// Accept transfers of the given port. Payload beyond extent is discarded (implicit truncation),
+    /// CRC is still checked over the whole transfer. Calling it again updates extent of the existing
+    /// subscription, new extent is applied starting from the next transfer.
+    pub fn subscribe(&mut self, port_id: PortId, extent: usize) -> Result<(), Error> {
+        match self.subscriptions.iter_mut().find(|s| s.port_id == port_id) {
+            Some(subscription) => {
+                subscription.extent = extent;
+                Ok(())
+            }
+            None => self.subscriptions
+                .push(Subscription { port_id, extent })
+                .map_err(|_| Error::TooManySubscriptions)
+        }
+    }
+
+    /// Stop accepting transfers of the given port, partially received ones are dropped.
+    /// Already completed transfers can still be popped. Returns false if port wasn't subscribed to.
+    pub fn unsubscribe(&mut self, port_id: PortId) -> bool {
+        let i = match self.subscriptions.iter().position(|s| s.port_id == port_id) {
+            Some(i) => i,
+            None => return false
+        };
+        self.subscriptions.swap_remove(i);
+        let mut keys: Vec<TransfersMapKey, MAX_TRANSFERS> = Vec::new();
+        for (key, transfer) in &mut self.transfers {
+            if key.kind.port_id() == port_id {
+                transfer.remove_pieces(&mut self.storage);
+                let _ = keys.push(*key);
+            }
+        }
+        for key in keys {
+            self.transfers.remove(&key);
+        }
+        true
+    }
+
+    /// Process incoming frame with tail byte.
+    /// Returns TransferComplete when a transfer is ready to be popped, or the reason why the frame
+    /// was rejected.
+    pub fn process_frame(&mut self, id: CanId, payload: &[u8], time_now: TimerInstantU32<TIMER_HZ>) -> Result<ProcessOutcome, ProcessError> {
+        self.process_frame_from(0, id, payload, time_now)
+    }
+
+    /// Process incoming frame received on one of the redundant interfaces. Transfer is assembled from
+    /// the interface its first frame was received on, frames from other interfaces are rejected with
+    /// InterfaceMismatch until it is completed. Trailing frames from a lagging interface that arrive after
+    /// completion are rejected the same way and do not affect the session.
+    pub fn process_frame_from(&mut self, iface: u8, id: CanId, payload: &[u8], time_now: TimerInstantU32<TIMER_HZ>) -> Result<ProcessOutcome, ProcessError> {
+        let time_now = time_now.ticks();
+        self.age_out(time_now);
+        let key: TransfersMapKey = id.into();
+        let result = self.process_frame_inner(key, iface, id, payload, time_now);
+        self.counters.count(result);
+        #[cfg(feature = "session-stats")]
+        if let Some(transfer) = self.transfers.get_mut(&key) {
+            transfer.stats.count(result, time_now);
+        }
+        result
+    }
+
+    fn process_frame_inner(&mut self, key: TransfersMapKey, iface: u8, id: CanId, payload: &[u8], time_now: u32) -> Result<ProcessOutcome, ProcessError> {
+        let port_id = id.transfer_kind.port_id();
+        let extent = match self.subscriptions.iter().find(|s| s.port_id == port_id) {
+            Some(subscription) => subscription.extent,
+            None => return Err(ProcessError::NotSubscribed)
+        };
+        let is_anonymous = match id.transfer_kind {
+            TransferKind::Message(message) => message.is_anonymous,
+            TransferKind::Service(service) => {
+                if !self.config.promiscuous && Some(service.destination_node_id) != self.config.local_node_id {
+                    return Err(ProcessError::NotForThisNode);
+                }
+                false
+            }
+        };
+        if is_anonymous {
+            if let Some(&tail_byte) = payload.last() {
+                match TailByte::from(tail_byte).kind {
+                    Kind::SingleFrame => {}
+                    Kind::SingleFrameV0 | Kind::MultiFrameV0 => return Err(ProcessError::UnsupportedVersion),
+                    _ => return Err(ProcessError::AnonymousMultiFrame),
+                }
+            }
+        }
+
         // Remove outdated transfers (if any) to clean up space
         if self.storage.len() == MAX_PIECES {
             self.remove_outdated_transfers(time_now);
         }
+        // Lower priority transfer that can be evicted if this frame doesn't fit
+        let victim_candidate = if self.storage.len() == MAX_PIECES {
+            self.find_eviction_victim(key, id.priority, time_now)
+        } else {
+            None
+        };
+        let mut victim = victim_candidate;
 
-        let key: TransfersMapKey = id.into();
-        if !self.transfers.contains_key(&key) {
-            if self.transfers.len() >= MAX_TRANSFERS {
+        // Pseudo node ID of an anonymous transfer is random, such transfers are not tracked in the session table
+        let mut anonymous = if is_anonymous {
+            Some(Transfer::new(id.priority, extent, time_now))
+        } else {
+            None
+        };
+        if !is_anonymous && !self.transfers.contains_key(&key) {
+            if self.transfers.len() >= MAX_TRANSFERS && !self.remove_stale_session(time_now) {
                 // No space left in transfers map
-                // TODO: count
-                return;
+                return Err(ProcessError::SessionTableFull);
             }
-            let transfer = Transfer::new(id.priority, self.latest_sequence_number, time_now);
-            self.latest_sequence_number = self.latest_sequence_number.wrapping_add(1);
+            let transfer = Transfer::new(id.priority, extent, time_now);
             // Will not fail because of the check above
             let _ = self.transfers.insert(key, transfer);
         }
-        let transfer = match self.transfers.get_mut(&key) {
+        let transfer = match anonymous.as_mut() {
             Some(t) => t,
-            _ => unreachable!(),
+            None => match self.transfers.get_mut(&key) {
+                Some(t) => t,
+                _ => unreachable!(),
+            },
         };
         transfer.last_changed_timestamp = time_now;
 
-        match Self::drive_state_machine(&mut self.storage, transfer, payload, &mut self.counters) {
-            Ok(_) => {
-
+        let result = Self::drive_state_machine(&mut self.storage, transfer, iface, id.priority, extent, payload, time_now, &self.config, &mut self.counters, &mut victim);
+        if let (Some(victim_piece_idx), None) = (victim_candidate, victim) {
+            self.fail_evicted_transfer(key, victim_piece_idx);
+        }
+        let outcome = result?;
+        let transfer = match anonymous.as_mut() {
+            Some(t) => t,
+            None => match self.transfers.get_mut(&key) {
+                Some(t) => t,
+                _ => unreachable!(),
             },
-            Err(_) => {
+        };
 
-            }
+        match transfer.take_completed(key, self.latest_sequence_number) {
+            Some(completed) => match self.ready.push(completed) {
+                Ok(_) => {
+                    if completed.frame_count == 1 {
+                        self.counters.single_frame_transfers += 1;
+                    }
+                    self.latest_sequence_number = self.latest_sequence_number.wrapping_add(1);
+                    Ok(ProcessOutcome::TransferComplete)
+                }
+                Err(completed) => {
+                    // Do not overwrite undelivered transfers, drop the new one instead
+                    self.storage.remove_all(completed.first_piece_idx);
+                    Err(ProcessError::ReadyQueueFull)
+                }
+            },
+            None => Ok(outcome)
         }
     }
 
-    fn drive_state_machine(storage: &mut PiecesStorage<MTU_M1, MAX_PIECES>, transfer: &mut Transfer<MTU>, payload: &[u8], counters: &mut Counters) -> Result<(), ()> {
+    #[allow(clippy::too_many_arguments)]
+    fn drive_state_machine(
+        storage: &mut PiecesStorage<MTU_M1, MAX_PIECES>,
+        transfer: &mut Transfer<MTU>,
+        iface: u8,
+        priority: Priority,
+        extent: usize,
+        payload: &[u8],
+        time_now: u32,
+        config: &Config,
+        counters: &mut Counters,
+        victim: &mut Option<PieceIdx>,
+    ) -> Result<ProcessOutcome, ProcessError> {
         let mut payload_owned = [0u8; MTU_M1];
-        let (payload_kind, tail_byte) = if payload.len() >= 1 && payload.len() <= MTU {
-            let tail_byte = Some(TailByte::from(*payload.last().unwrap()));
-            if payload.len() == 1 {
-                (PayloadKind::Empty, tail_byte)
-            } else if payload.len() < MTU {
-                transfer.last_piece_len = payload.len() as PieceByteIdx;
-                payload_owned[0..payload.len() - 1].copy_from_slice(&payload[0..payload.len() - 1]);
-                (PayloadKind::LessThanMTU, tail_byte)
-            } else {
-                transfer.last_piece_len = payload.len() as PieceByteIdx;
-                payload_owned.copy_from_slice(&payload[0..payload.len() - 1]);
-                (PayloadKind::ExactlyMTU, tail_byte)
-            }
+        // CAN FD frames can only be of certain lengths, last frame is padded to the nearest one
+        let is_valid_len = !payload.is_empty() && payload.len() <= MTU && is_valid_frame_len(payload.len());
+        let tail_byte = if is_valid_len {
+            payload_owned[0..payload.len() - 1].copy_from_slice(&payload[0..payload.len() - 1]);
+            Some(TailByte::from(*payload.last().unwrap()))
         } else {
-            (PayloadKind::Invalid, None)
+            None
         };
 
-        let output = transfer.transfer_machine.advance(payload_kind, tail_byte);
+        // Retransmitted or redundant copy of an already received transfer
+        if let Some(tail_byte) = tail_byte {
+            if tail_byte.is_start() && transfer.is_duplicate(tail_byte.id, time_now, config.transfer_id_timeout) {
+                return Ok(ProcessOutcome::DuplicateTransfer);
+            }
+            // Same transfer received on a redundant interface
+            if transfer.transfer_machine.is_assembling() && iface != transfer.iface &&
+                (!tail_byte.is_start() || Some(tail_byte.id) == transfer.transfer_machine.transfer_id) {
+                return Err(ProcessError::InterfaceMismatch);
+            }
+            // Frame from another transfer, do not let it be spliced into the ongoing one
+            if !tail_byte.is_start() && transfer.transfer_machine.is_assembling() {
+                if Some(tail_byte.id) != transfer.transfer_machine.transfer_id {
+                    return Err(ProcessError::TransferIdMismatch);
+                }
+                if priority != transfer.priority {
+                    return Err(ProcessError::PriorityMismatch);
+                }
+            }
+            if config.ignore_duplicate_frames && transfer.is_duplicate_frame(priority, payload) {
+                return Ok(ProcessOutcome::DuplicateFrame);
+            }
+            // Trailing frames of an already received transfer, e.g. from a lagging redundant interface
+            if !tail_byte.is_start() && transfer.transfer_machine.state == State::Done &&
+                transfer.is_duplicate(tail_byte.id, time_now, config.transfer_id_timeout) {
+                return if iface != transfer.iface {
+                    Err(ProcessError::InterfaceMismatch)
+                } else {
+                    Ok(ProcessOutcome::DuplicateTransfer)
+                };
+            }
+        }
+
+        // MTU is per transfer, pieces of a Classic CAN transfer only use 7 bytes of each slot
+        let output = transfer.transfer_machine.advance(payload.len(), tail_byte);
+        let is_start = tail_byte.map(|t| t.is_start()).unwrap_or(false);
+        if is_start && (output == TransferMachineOutput::Push || output == TransferMachineOutput::Restart) {
+            transfer.transfer_timestamp = time_now;
+            transfer.priority = priority;
+            transfer.extent = extent;
+            transfer.iface = iface;
+        }
         // If more space is needed, lower priority transfer storage will be wiped and exactly one
         // freed up slot will be used for this one.
-        // first_piece index of that l.p. transfer is returned in such case, it is then looked up
-        // and marked as Failure
+        // victim is taken in such case, transfer owning it is then looked up and marked as Failure
         use TransferMachineOutput::*;
         match output {
-            Ignore => {}
-            Push | CheckCrcAndPush => {
+            Ignore(e) => Err(e),
+            Push | CheckCrcAndPush | Restart => {
+                if output == Restart {
+                    transfer.remove_pieces(storage);
+                    counters.interrupted_transfers += 1;
+                }
+                transfer.frame_count += 1;
+                // Residue of data followed by its crc is zero, padding is covered as well.
+                // Computed for single frame transfers too, but not checked.
+                transfer.crc.add(&payload[..payload.len() - 1]);
+                transfer.total_len += payload.len() - 1;
                 if output == CheckCrcAndPush {
-                    let mut crc16 = crc_any::CRCu16::crc16ccitt_false();
-                    let mut received_crc16 = [0, 0];
-                    for (chunk, is_last) in storage.traverse(transfer.first_piece_idx.unwrap()) {
-                        if is_last {
-                            match payload.len() {
-                                0 => {
-                                    unreachable!()
-                                },
-                                1 => {
-                                    unreachable!()
-                                },
-                                2 => {
-                                    crc16.digest(&chunk[..chunk.len() - 1]);
-                                    crc16.digest(&[payload[0]]);
-                                    received_crc16[0] = *chunk.last().unwrap();
-                                    received_crc16[1] = payload[0];
-                                },
-                                3 => {
-                                    crc16.digest(chunk);
-                                    received_crc16[0] = payload[0];
-                                    received_crc16[1] = payload[1];
-                                },
-                                _ => {
-                                    crc16.digest(chunk);
-                                    crc16.digest(&payload[0..payload.len() - 3]);
-                                    received_crc16[0] = payload[payload.len() - 2];
-                                    received_crc16[1] = payload[payload.len() - 1];
-                                }
-                            }
-                        } else {
-                            crc16.digest(chunk);
-                        }
-                    }
-                    let received_crc16 = u16::from_be_bytes(received_crc16);
-                    if crc16.get_crc() != received_crc16 {
-                        transfer.first_piece_idx.map(|idx| storage.remove_all(idx));
-                        counters.transfers_with_bad_crc += 1;
-                        return Ok(())
-                    } else {
-                        counters.transfers_with_good_crc += 1;
+                    if !transfer.crc.is_residue_zero() {
+                        transfer.remove_pieces(storage);
+                        transfer.transfer_machine.fail();
+                        return Err(ProcessError::BadCrc);
                     }
+                    counters.transfers_with_good_crc += 1;
                 }
 
-                match transfer.last_piece_idx {
-                    Some(idx) => {
-                        transfer.last_piece_idx = match storage.push_after(payload_owned, idx) {
-                            Ok(idx) => Some(idx),
-                            Err(_) => {
-                                return Err(());
-                            }
-                        };
+                // Implicit truncation: data beyond extent is not stored, but is still covered by crc
+                if transfer.first_piece_idx.is_some() && transfer.received_len >= transfer.extent {
+                    transfer.truncated = true;
+                }
+                if !transfer.truncated {
+                    transfer.last_piece_len = (payload.len() - 1) as PieceByteIdx;
+                    transfer.received_len += payload.len() - 1;
+                    if storage.len() == MAX_PIECES {
+                        if let Some(victim) = victim.take() {
+                            storage.remove_all(victim);
+                        }
                     }
-                    None => match storage.push(payload_owned) {
-                        Ok(idx) => {
+                    let pushed = match transfer.last_piece_idx {
+                        Some(idx) => storage.push_after(payload_owned, idx).map(|idx| {
+                            transfer.last_piece_idx = Some(idx);
+                        }),
+                        None => storage.push(payload_owned).map(|idx| {
                             transfer.first_piece_idx = Some(idx);
                             transfer.last_piece_idx = Some(idx);
-                        }
-                        _ => {
-                            return Err(());
-                        }
-                    },
+                        }),
+                    };
+                    if pushed.is_err() {
+                        // Transfer with a missing piece is of no use
+                        transfer.remove_pieces(storage);
+                        transfer.transfer_machine.fail();
+                        return Err(ProcessError::OutOfStorage);
+                    }
+                }
+                if config.ignore_duplicate_frames {
+                    transfer.set_last_frame(payload);
+                }
+                if transfer.transfer_machine.state == State::Done {
+                    transfer.last_transfer = transfer.transfer_machine.transfer_id
+                        .map(|id| (id, transfer.transfer_timestamp));
                 }
+                Ok(ProcessOutcome::Accepted)
             }
-            Drop => {
-                transfer.first_piece_idx.map(|idx| storage.remove_all(idx));
+            Drop(e) => {
+                transfer.remove_pieces(storage);
                 counters.dropped_frames += 1;
-                // self.first_piece_idx = None;
-                // self.last_piece_idx = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// First piece of the oldest in-progress transfer among the lowest priority ones, which are lower
+    /// than the given priority.
+    fn find_eviction_victim(&self, except: TransfersMapKey, higher_than: Priority, time_now: u32) -> Option<PieceIdx> {
+        let mut victim: Option<(&Transfer<MTU>, PieceIdx)> = None;
+        for (key, transfer) in &self.transfers {
+            let first_piece_idx = match transfer.first_piece_idx {
+                Some(idx) if *key != except && transfer.priority < higher_than && transfer.transfer_machine.is_assembling() => idx,
+                _ => continue
+            };
+            let is_better = match victim {
+                Some((v, _)) => {
+                    transfer.priority < v.priority || (transfer.priority == v.priority &&
+                        elapsed(time_now, transfer.transfer_timestamp) > elapsed(time_now, v.transfer_timestamp))
+                }
+                None => true
+            };
+            if is_better {
+                victim = Some((transfer, first_piece_idx));
+            }
+        }
+        victim.map(|(_, idx)| idx)
+    }
+
+    /// Mark transfer which pieces were wiped to make space for a higher priority one as failed
+    fn fail_evicted_transfer(&mut self, except: TransfersMapKey, first_piece_idx: PieceIdx) {
+        for (key, transfer) in &mut self.transfers {
+            if *key != except && transfer.first_piece_idx == Some(first_piece_idx) {
+                transfer.forget_pieces();
+                transfer.transfer_machine.fail();
+                self.counters.evicted_transfers += 1;
+                break;
+            }
+        }
+    }
+
+    fn transfer_lifetime() -> u32 {
+        TimerDurationU32::<TIMER_HZ>::millis(TRANSFER_LIFETIME_MS).ticks()
+    }
+
+    /// Remove everything past its deadline: pieces of transfers that didn't receive any frames for
+    /// TRANSFER_LIFETIME_MS, sessions idle for longer than transfer-ID timeout and completed transfers
+    /// not popped in time (see set_ready_transfer_timeout). Meant to be called periodically, at least once
+    /// per eighth of the timer range if frames are not received that often (see Assembler).
+    pub fn expire(&mut self, time_now: TimerInstantU32<TIMER_HZ>) -> ExpireStats {
+        let time_now = time_now.ticks();
+        self.age_out(time_now);
+        let lifetime = Self::transfer_lifetime();
+        let mut stats = ExpireStats::default();
+        let mut expired: Vec<TransfersMapKey, MAX_TRANSFERS> = Vec::new();
+        for (key, transfer) in &mut self.transfers {
+            let idle_for = elapsed(time_now, transfer.last_changed_timestamp);
+            if transfer.transfer_machine.is_assembling() && idle_for > lifetime {
+                stats.pieces += transfer.remove_pieces(&mut self.storage) as usize;
+                transfer.transfer_machine.fail();
+                stats.transfers += 1;
+            }
+            // Session is only needed to detect duplicates after transfer is received
+            if !transfer.transfer_machine.is_assembling() && idle_for > self.config.transfer_id_timeout {
+                let _ = expired.push(*key);
             }
         }
-        Ok(())
+        stats.sessions = expired.len();
+        for key in expired {
+            self.transfers.remove(&key);
+        }
+        if let Some(timeout) = self.config.ready_transfer_timeout {
+            let mut i = 0;
+            while i < self.ready.len() {
+                if elapsed(time_now, self.ready[i].last_frame_timestamp) > timeout {
+                    let transfer = self.ready.swap_remove(i);
+                    stats.pieces += self.storage.remove_all(transfer.first_piece_idx) as usize;
+                    stats.ready_transfers += 1;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.counters.expired_transfers += stats.transfers + stats.ready_transfers;
+        stats
     }
 
-    // fn fail_lower_priority_transfer(&mut self, lower_than: Priority, first_piece_idx: PieceIdx) {
-    //     for (_, transfer) in &mut self.transfers {
-    //         match transfer.transfer_machine.first_piece_idx {
-    //             Some(idx) => {
-    //                 if transfer.priority < lower_than && idx == first_piece_idx {
-    //                     transfer.transfer_machine.fail();
-    //                 }
-    //             }
-    //             None => {}
-    //         }
-    //     }
-    // }
+    /// Earliest instant at which expire() will remove something, or None if there is nothing to expire.
+    /// Useful to arm a wake-up timer instead of calling expire() periodically.
+    pub fn next_deadline(&self, time_now: TimerInstantU32<TIMER_HZ>) -> Option<TimerInstantU32<TIMER_HZ>> {
+        let time_now = time_now.ticks();
+        // Ticks left until expire() considers something outdated (it uses strict comparison)
+        let left = |since: u32, timeout: u32| (timeout as u64 + 1).saturating_sub(elapsed(time_now, since) as u64);
+        let lifetime = Self::transfer_lifetime();
+        let sessions = self.transfers.values().map(|transfer| {
+            let idle_timeout = if transfer.transfer_machine.is_assembling() {
+                lifetime
+            } else {
+                self.config.transfer_id_timeout
+            };
+            left(transfer.last_changed_timestamp, idle_timeout)
+        });
+        let ready = self.ready.iter().filter_map(|transfer| {
+            self.config.ready_transfer_timeout.map(|timeout| left(transfer.last_frame_timestamp, timeout))
+        });
+        sessions
+            .chain(ready)
+            .min()
+            .map(|left| TimerInstantU32::from_ticks(time_now.wrapping_add(left as u32)))
+    }
+
+    /// Remove sessions idle for AGE_OUT_IDLE_TICKS, so that they are not considered recent once
+    /// the timer wraps around.
+    fn age_out(&mut self, time_now: u32) {
+        // Not elapsed(), a long pause between calls must trigger the check as well
+        if time_now.wrapping_sub(self.last_age_out) < AGE_OUT_CHECK_TICKS {
+            return;
+        }
+        self.last_age_out = time_now;
+        let mut aged_out: Vec<TransfersMapKey, MAX_TRANSFERS> = Vec::new();
+        for (key, transfer) in &mut self.transfers {
+            if elapsed(time_now, transfer.last_changed_timestamp) > AGE_OUT_IDLE_TICKS {
+                if transfer.transfer_machine.is_assembling() {
+                    self.counters.expired_transfers += 1;
+                }
+                transfer.remove_pieces(&mut self.storage);
+                let _ = aged_out.push(*key);
+            }
+        }
+        for key in aged_out {
+            self.transfers.remove(&key);
+        }
+    }
 
     fn remove_outdated_transfers(&mut self, time_now: u32) {
+        let lifetime = Self::transfer_lifetime();
         for (_, transfer) in &mut self.transfers {
-            if time_now - transfer.last_changed_timestamp > TRANSFER_LIFETIME {
-                match transfer.first_piece_idx {
-                    Some(idx) => {
-                        if self.storage.remove_all(idx) >= 1 {
-                            // Destroy only this transfer, since at least one slot is now free for new data
-                            // and allow user to read out old transfers if it is slow
-                            break;
-                        }
-                        transfer.transfer_machine.fail();
-                    },
-                    None => {}
+            if elapsed(time_now, transfer.last_changed_timestamp) > lifetime {
+                let removed = transfer.remove_pieces(&mut self.storage);
+                if removed >= 1 {
+                    transfer.transfer_machine.fail();
+                    // Destroy only this transfer, since at least one slot is now free for new data
+                    break;
                 }
             }
         }
     }
 
-    fn highest_priority_ready_transfer(&self) -> Option<TransfersMapKey> {
-        let mut highest: Option<(TransfersMapKey, Priority, TransferSeq)> = None;
-        for (key, transfer) in &self.transfers {
-            if transfer.transfer_machine.state != State::Done {
-                continue;
+    /// Remove one idle session which didn't receive anything for longer than transfer-ID timeout,
+    /// returns true if one was removed.
+    fn remove_stale_session(&mut self, time_now: u32) -> bool {
+        let stale = self.transfers.iter().find(|(_, transfer)| {
+            !transfer.transfer_machine.is_assembling() && elapsed(time_now, transfer.last_changed_timestamp) > self.config.transfer_id_timeout
+        }).map(|(key, _)| *key);
+        match stale {
+            Some(key) => {
+                self.transfers.remove(&key);
+                true
             }
+            None => false
+        }
+    }
+
+    fn highest_priority_ready_transfer(&self) -> Option<usize> {
+        let mut highest: Option<(usize, Priority, TransferSeq)> = None;
+        for (i, transfer) in self.ready.iter().enumerate() {
             highest = match highest {
-                Some((highest_key, highest_priority, highest_seq_number)) => {
+                Some((highest_i, highest_priority, highest_seq_number)) => {
                     if transfer.priority > highest_priority {
-                        Some((*key, transfer.priority, transfer.sequence_number))
+                        Some((i, transfer.priority, transfer.sequence_number))
                     } else if transfer.priority == highest_priority &&
                         transfer.sequence_number.wrapping_sub(highest_seq_number) < 0 {
-                        Some((*key, transfer.priority, transfer.sequence_number))
+                        Some((i, transfer.priority, transfer.sequence_number))
                     } else {
-                        Some((highest_key, highest_priority, highest_seq_number))
+                        Some((highest_i, highest_priority, highest_seq_number))
                     }
                 },
                 None => {
-                    Some((*key, transfer.priority, transfer.sequence_number))
+                    Some((i, transfer.priority, transfer.sequence_number))
                 }
             };
         }
-        highest.map(|(key, _, _)| key)
+        highest.map(|(i, _, _)| i)
     }
 
-    pub fn pop<'a>(&mut self, assembly_buffer: &'a mut[u8]) -> Option<ReadyTransfer<'a>> {
-        self.highest_priority_ready_transfer().map(move |h| {
-            let transfer = self.transfers.get_mut(&h).expect("");
-            let payload_len = if let Some(idx) = transfer.first_piece_idx {
-                let mut buf_idx = 0;
-                for (chunk, _) in self.storage.traverse(idx) {
-                    assembly_buffer[buf_idx..buf_idx + chunk.len()].copy_from_slice(chunk);
-                    buf_idx += chunk.len();
-                }
-                buf_idx
-            } else {
-                0
-            };
-            let priority = transfer.priority;
-            self.transfers.remove(&h);
-            ReadyTransfer {
-                source: h.source,
-                kind: h.kind,
-                priority,
-                payload: &assembly_buffer[..payload_len]
-            }
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Statistics of a session, if it is still tracked.
+    #[cfg(feature = "session-stats")]
+    pub fn session_stats(&self, source: NodeId, kind: TransferKind) -> Option<SessionStats<TIMER_HZ>> {
+        self.transfers.get(&TransfersMapKey { kind, source }).map(|t| t.stats.into())
+    }
+
+    /// Statistics of all currently tracked sessions.
+    /// Statistics are lost when an idle session is removed to make room for a new one.
+    #[cfg(feature = "session-stats")]
+    pub fn sessions_stats(&self) -> impl Iterator<Item = (NodeId, TransferKind, SessionStats<TIMER_HZ>)> + '_ {
+        self.transfers.iter().map(|(key, t)| (key.source, key.kind, t.stats.into()))
+    }
+
+    /// Pop highest priority transfer and copy its payload into assembly_buffer.
+    /// If assembly_buffer is too small, BufferTooSmall is returned and the transfer stays in the queue,
+    /// so that it can be popped with a larger buffer or with pop_ref().
+    pub fn pop<'a>(&mut self, assembly_buffer: &'a mut[u8]) -> Result<Option<ReadyTransfer<'a, TIMER_HZ>>, Error> {
+        let i = match self.highest_priority_ready_transfer() {
+            Some(i) => i,
+            None => return Ok(None)
+        };
+        if self.ready[i].payload_len > assembly_buffer.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        let transfer = match self.pop_ref() {
+            Some(transfer) => transfer,
+            None => return Ok(None)
+        };
+        let len = transfer.copy_to(assembly_buffer)?;
+        Ok(Some(ReadyTransfer {
+            source: transfer.source,
+            kind: transfer.kind,
+            priority: transfer.priority,
+            transfer_id: transfer.transfer_id,
+            first_frame_timestamp: transfer.first_frame_timestamp,
+            last_frame_timestamp: transfer.last_frame_timestamp,
+            frame_count: transfer.frame_count,
+            iface: transfer.iface,
+            payload: &assembly_buffer[..len]
+        }))
+    }
+
+    /// Pop highest priority transfer without copying, payload stays in storage until the returned
+    /// transfer is dropped.
+    pub fn pop_ref(&mut self) -> Option<RefReadyTransfer<'_, MTU_M1, MAX_PIECES, TIMER_HZ>> {
+        let i = self.highest_priority_ready_transfer()?;
+        let transfer = self.ready.swap_remove(i);
+        Some(RefReadyTransfer {
+            source: transfer.key.source,
+            kind: transfer.key.kind,
+            priority: transfer.priority,
+            transfer_id: transfer.transfer_id,
+            first_frame_timestamp: TimerInstantU32::from_ticks(transfer.first_frame_timestamp),
+            last_frame_timestamp: TimerInstantU32::from_ticks(transfer.last_frame_timestamp),
+            frame_count: transfer.frame_count,
+            iface: transfer.iface,
+            storage: &mut self.storage,
+            first_piece_idx: transfer.first_piece_idx,
+            piece_len: transfer.piece_len as usize,
+            payload_len: transfer.payload_len,
         })
     }
+
+    /// Check that piece storage is consistent: every transfer's chain of pieces is intact, pieces are not
+    /// shared between transfers and there are no leaked pieces.
+    #[cfg(any(test, debug_assertions))]
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let in_progress = self.transfers.values()
+            .filter_map(|t| t.first_piece_idx.map(|first| (first, t.last_piece_idx)));
+        let ready = self.ready.iter().map(|t| (t.first_piece_idx, None));
+        self.storage.validate(in_progress.chain(ready))
+    }
+}
+
+#[cfg(any(test, debug_assertions))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ValidationError {
+    /// Chain of pieces points to an empty slot or ends not where expected
+    BrokenChain,
+    /// Chain of pieces loops onto itself or overlaps with another one
+    CyclicChain,
+    /// Piece doesn't belong to any transfer
+    OrphanedPiece,
+    /// Amount of used pieces doesn't match the amount of filled slots
+    WrongUsedCount,
+    /// List of empty pieces is looped, contains filled pieces or doesn't contain all the empty ones
+    BrokenFreeList,
 }
 
-pub struct ReadyTransfer<'a> {
+/// Frame was processed without errors.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ProcessOutcome {
+    /// Frame was added to a transfer that is still being assembled
+    Accepted,
+    /// Frame completed a transfer, it can be popped now
+    TransferComplete,
+    /// Start of a transfer that was already received within transfer-ID timeout, ignored
+    DuplicateTransfer,
+    /// Exact copy of the previous frame, ignored
+    DuplicateFrame,
+}
+
+/// Reason why a frame was rejected. Transfer that was being assembled from the same session may
+/// be dropped as well, except when the frame was rejected before reaching its session.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ProcessError {
+    /// Port is not subscribed to, frame ignored
+    NotSubscribed,
+    /// Service transfer addressed to another node, frame ignored
+    NotForThisNode,
+    /// Frame is empty, longer than MTU or not of a valid CAN FD length
+    MalformedFrame,
+    /// UAVCAN v0 tail byte
+    UnsupportedVersion,
+    /// Frame is out of order, toggle bit is the same as in the previous one
+    ToggleError,
+    /// Continuation of a transfer which start was not received
+    MissingStart,
+    /// Frame length doesn't match MTU of the transfer being assembled
+    UnexpectedLength,
+    /// Frame belongs to another transfer from the same session, ignored
+    TransferIdMismatch,
+    /// Frame has different priority than the start of the transfer, ignored
+    PriorityMismatch,
+    /// Frame of a transfer being assembled from another redundant interface, ignored
+    InterfaceMismatch,
+    /// Anonymous transfers can only be single frame, frame ignored
+    AnonymousMultiFrame,
+    /// Multi-frame transfer was completed, but CRC doesn't match
+    BadCrc,
+    /// All MAX_TRANSFERS sessions are in use, frame ignored
+    SessionTableFull,
+    /// No free pieces left in storage, transfer dropped
+    OutOfStorage,
+    /// Transfer was completed, but ready queue is full, transfer dropped
+    ReadyQueueFull,
+}
+
+pub struct ReadyTransfer<'a, const TIMER_HZ: u32> {
     pub source: NodeId,
     pub kind: TransferKind,
     pub priority: Priority,
+    pub transfer_id: TransferId,
+    /// Reception time of the first frame, this is the transfer timestamp
+    pub first_frame_timestamp: TimerInstantU32<TIMER_HZ>,
+    pub last_frame_timestamp: TimerInstantU32<TIMER_HZ>,
+    pub frame_count: usize,
+    /// Interface the transfer was received on, see Assembler::process_frame_from
+    pub iface: u8,
     pub payload: &'a [u8],
 }
 
+/// What was removed by Assembler::expire()
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+pub struct ExpireStats {
+    /// Sessions removed from the session table
+    pub sessions: usize,
+    /// Partially received transfers dropped
+    pub transfers: usize,
+    /// Completed, but not popped transfers dropped
+    pub ready_transfers: usize,
+    /// Storage pieces freed
+    pub pieces: usize,
+}
+
+/// Completed transfer borrowed from the assembler, its pieces are freed when it is dropped.
+pub struct RefReadyTransfer<'a, const MTU_M1: usize, const MAX_PIECES: usize, const TIMER_HZ: u32> {
+    pub source: NodeId,
+    pub kind: TransferKind,
+    pub priority: Priority,
+    pub transfer_id: TransferId,
+    /// Reception time of the first frame, this is the transfer timestamp
+    pub first_frame_timestamp: TimerInstantU32<TIMER_HZ>,
+    pub last_frame_timestamp: TimerInstantU32<TIMER_HZ>,
+    pub frame_count: usize,
+    /// Interface the transfer was received on, see Assembler::process_frame_from
+    pub iface: u8,
+    storage: &'a mut PiecesStorage<MTU_M1, MAX_PIECES>,
+    first_piece_idx: PieceIdx,
+    /// Amount of data bytes in each piece, last one might be used partially
+    piece_len: usize,
+    payload_len: usize,
+}
+
+impl<'a, const MTU_M1: usize, const MAX_PIECES: usize, const TIMER_HZ: u32> RefReadyTransfer<'a, MTU_M1, MAX_PIECES, TIMER_HZ> {
+    pub fn len(&self) -> usize {
+        self.payload_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payload_len == 0
+    }
+
+    /// Payload in the order it was received, one chunk per frame.
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        let piece_len = self.piece_len;
+        self.storage.traverse(self.first_piece_idx).scan(self.payload_len, move |remaining, (chunk, _)| {
+            if *remaining == 0 {
+                return None;
+            }
+            let len = core::cmp::min(piece_len, *remaining);
+            *remaining -= len;
+            Some(&chunk[..len])
+        })
+    }
+
+    /// Copy payload into buf and return its length, buf must be at least len() bytes long.
+    pub fn copy_to(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() < self.payload_len {
+            return Err(Error::BufferTooSmall);
+        }
+        let mut buf_idx = 0;
+        for chunk in self.chunks() {
+            buf[buf_idx..buf_idx + chunk.len()].copy_from_slice(chunk);
+            buf_idx += chunk.len();
+        }
+        Ok(buf_idx)
+    }
+}
+
+impl<'a, const MTU_M1: usize, const MAX_PIECES: usize, const TIMER_HZ: u32> Drop for RefReadyTransfer<'a, MTU_M1, MAX_PIECES, TIMER_HZ> {
+    fn drop(&mut self) {
+        self.storage.remove_all(self.first_piece_idx);
+    }
+}
+
 impl<
     const MTU: usize,
     const MTU_M1: usize,
     const MAX_PIECES: usize,
     const MAX_TRANSFERS: usize,
-    const TRANSFER_LIFETIME: u32,
-> Display for Assembler<MTU, MTU_M1, MAX_PIECES, MAX_TRANSFERS, TRANSFER_LIFETIME>
+    const MAX_READY: usize,
+    const MAX_SUBSCRIPTIONS: usize,
+    const TIMER_HZ: u32,
+    const TRANSFER_LIFETIME_MS: u32,
+> Display for Assembler<MTU, MTU_M1, MAX_PIECES, MAX_TRANSFERS, MAX_READY, MAX_SUBSCRIPTIONS, TIMER_HZ, TRANSFER_LIFETIME_MS>
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         writeln!(f, "Transfers:").ok();
@@ -286,6 +851,10 @@ impl<
                 .ok();
             i += 1;
         }
+        writeln!(f, "Ready:").ok();
+        for transfer in &self.ready {
+            writeln!(f, "{}", transfer).ok();
+        }
         writeln!(f, "Storage:").ok();
         write!(f, "{}", self.storage).ok();
         writeln!(f, "{:?}", self.counters)
@@ -294,10 +863,144 @@ impl<
 
 #[derive(Copy, Clone, Default, Debug)]
 pub struct Counters {
+    /// Frames added to a transfer being assembled (including the ones that completed it)
+    pub accepted_frames: usize,
+    /// Transfers moved into the ready queue
+    pub completed_transfers: usize,
+    /// Multi-frame transfers that passed CRC check
     pub transfers_with_good_crc: usize,
+    /// Single frame transfers moved into the ready queue
     pub single_frame_transfers: usize,
+    /// Multi-frame transfers that failed CRC check and were dropped
     pub transfers_with_bad_crc: usize,
+    /// Frames that caused a partially received transfer to be dropped
     pub dropped_frames: usize,
+    /// Transfers ignored because the same transfer ID was received within transfer-ID timeout
+    pub duplicate_transfers: usize,
+    /// Frames with a transfer ID different from the one of the transfer being assembled
+    pub transfer_id_mismatch: usize,
+    /// Frames with a priority different from the one of the transfer being assembled
+    pub priority_mismatch: usize,
+    /// Frames of the current transfer received on a different redundant interface
+    pub interface_mismatch: usize,
+    /// Frames of anonymous transfers that are not single frame ones
+    pub anonymous_multi_frame: usize,
+    /// In-progress transfers abandoned by the sender, who started a new one instead
+    pub interrupted_transfers: usize,
+    /// In-progress transfers dropped to make space for higher priority ones
+    pub evicted_transfers: usize,
+    /// In-progress and not popped transfers removed by expire()
+    pub expired_transfers: usize,
+    /// Exact copies of the previous frame ignored, see Assembler::set_ignore_duplicate_frames()
+    pub duplicate_frames: usize,
+    /// Completed transfers dropped because the ready queue was full
+    pub ready_queue_overflows: usize,
+    /// Frames of ports without a subscription
+    pub not_subscribed: usize,
+    /// Service frames addressed to another node
+    pub not_for_this_node: usize,
+    /// Frames that are empty, longer than MTU or of an invalid CAN FD length
+    pub malformed_frames: usize,
+    /// Frames with UAVCAN v0 tail byte
+    pub unsupported_version: usize,
+    /// Frames with a wrong toggle bit
+    pub toggle_errors: usize,
+    /// Frames of a multi-frame transfer whose start wasn't received
+    pub missing_start: usize,
+    /// Frames with a length that doesn't match MTU of the transfer being assembled
+    pub unexpected_length: usize,
+    /// Frames from new sessions rejected because the session table was full
+    pub session_table_full: usize,
+    /// Frames rejected because piece storage was full
+    pub out_of_storage: usize,
+}
+
+impl Counters {
+    fn count(&mut self, result: Result<ProcessOutcome, ProcessError>) {
+        let counter = match result {
+            Ok(ProcessOutcome::Accepted) => &mut self.accepted_frames,
+            Ok(ProcessOutcome::TransferComplete) => {
+                self.accepted_frames += 1;
+                &mut self.completed_transfers
+            }
+            Ok(ProcessOutcome::DuplicateTransfer) => &mut self.duplicate_transfers,
+            Ok(ProcessOutcome::DuplicateFrame) => &mut self.duplicate_frames,
+            Err(ProcessError::NotSubscribed) => &mut self.not_subscribed,
+            Err(ProcessError::NotForThisNode) => &mut self.not_for_this_node,
+            Err(ProcessError::MalformedFrame) => &mut self.malformed_frames,
+            Err(ProcessError::UnsupportedVersion) => &mut self.unsupported_version,
+            Err(ProcessError::ToggleError) => &mut self.toggle_errors,
+            Err(ProcessError::MissingStart) => &mut self.missing_start,
+            Err(ProcessError::UnexpectedLength) => &mut self.unexpected_length,
+            Err(ProcessError::TransferIdMismatch) => &mut self.transfer_id_mismatch,
+            Err(ProcessError::PriorityMismatch) => &mut self.priority_mismatch,
+            Err(ProcessError::InterfaceMismatch) => &mut self.interface_mismatch,
+            Err(ProcessError::AnonymousMultiFrame) => &mut self.anonymous_multi_frame,
+            Err(ProcessError::BadCrc) => &mut self.transfers_with_bad_crc,
+            Err(ProcessError::SessionTableFull) => &mut self.session_table_full,
+            Err(ProcessError::OutOfStorage) => &mut self.out_of_storage,
+            Err(ProcessError::ReadyQueueFull) => &mut self.ready_queue_overflows,
+        };
+        *counter += 1;
+    }
+}
+
+/// Statistics of one session (source node and transfer kind).
+#[cfg(feature = "session-stats")]
+#[derive(Copy, Clone, Debug)]
+pub struct SessionStats<const TIMER_HZ: u32> {
+    pub accepted_frames: usize,
+    pub completed_transfers: usize,
+    /// Frames rejected for any reason, including the ones that failed CRC check
+    pub rejected_frames: usize,
+    pub transfers_with_bad_crc: usize,
+    /// Time when the last frame from this session was received
+    pub last_seen: TimerInstantU32<TIMER_HZ>,
+}
+
+/// SessionStats as stored alongside each session, timestamp is in timer ticks
+#[cfg(feature = "session-stats")]
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct RawSessionStats {
+    accepted_frames: usize,
+    completed_transfers: usize,
+    rejected_frames: usize,
+    transfers_with_bad_crc: usize,
+    last_seen: u32,
+}
+
+#[cfg(feature = "session-stats")]
+impl<const TIMER_HZ: u32> From<RawSessionStats> for SessionStats<TIMER_HZ> {
+    fn from(stats: RawSessionStats) -> Self {
+        SessionStats {
+            accepted_frames: stats.accepted_frames,
+            completed_transfers: stats.completed_transfers,
+            rejected_frames: stats.rejected_frames,
+            transfers_with_bad_crc: stats.transfers_with_bad_crc,
+            last_seen: TimerInstantU32::from_ticks(stats.last_seen),
+        }
+    }
+}
+
+#[cfg(feature = "session-stats")]
+impl RawSessionStats {
+    pub(crate) fn count(&mut self, result: Result<ProcessOutcome, ProcessError>, time_now: u32) {
+        match result {
+            Ok(ProcessOutcome::Accepted) => self.accepted_frames += 1,
+            Ok(ProcessOutcome::TransferComplete) => {
+                self.accepted_frames += 1;
+                self.completed_transfers += 1;
+            }
+            Ok(ProcessOutcome::DuplicateTransfer | ProcessOutcome::DuplicateFrame) => {}
+            Err(e) => {
+                self.rejected_frames += 1;
+                if e == ProcessError::BadCrc {
+                    self.transfers_with_bad_crc += 1;
+                }
+            }
+        }
+        self.last_seen = time_now;
+    }
 }
 
 #[cfg(test)]
@@ -305,7 +1008,15 @@ mod tests {
     extern crate std;
     use crate::types::*;
     use crate::slicer::{Slicer};
-    use crate::assembler::Assembler;
+    use crate::assembler::{Assembler, ProcessOutcome, ProcessError};
+    use crate::assembler::assembler::DEFAULT_TRANSFER_ID_TIMEOUT_MS;
+    use crate::Error;
+    use crate::assembler::assembler::ExpireStats;
+    use fugit::{TimerInstantU32, TimerDurationU32};
+
+    fn ms(t: u32) -> TimerInstantU32<1000> {
+        TimerInstantU32::from_ticks(t)
+    }
 
     #[test]
     fn check_single_frame() {
@@ -313,13 +1024,15 @@ mod tests {
         let mut slicer = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
         let transfer_bytes = slicer.next().unwrap();
 
-        let mut assembler = Assembler::<8, 7, 128, 8, 10>::new();
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
         let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
-        assembler.process_frame(id, &transfer_bytes, 0);
+        assembler.process_frame(id, &transfer_bytes, ms(0)).ok();
         assert_eq!(assembler.transfers.len(), 1);
+        assert_eq!(assembler.ready.len(), 1);
 
         let mut buffer = [0u8; 512];
-        let transfer = assembler.pop(&mut buffer);
+        let transfer = assembler.pop(&mut buffer).unwrap();
         assert!(transfer.is_some());
         let transfer = transfer.unwrap();
         assert_eq!(transfer.source, NodeId::new(3).unwrap());
@@ -328,11 +1041,774 @@ mod tests {
             is_anonymous: false
         }));
         assert_eq!(transfer.payload, &[0, 1, 2, 3, 4, 5, 6]);
-        assert!(assembler.pop(&mut buffer).is_none());
+        assert!(assembler.pop(&mut buffer).unwrap().is_none());
     }
 
     #[test]
     fn check_multi_frame() {
         let payload = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let slicer = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        for frame in slicer {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.counters.transfers_with_good_crc, 1);
+
+        let mut buffer = [0u8; 512];
+        let transfer = assembler.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(transfer.payload, &payload);
+    }
+
+    #[test]
+    fn check_crc_split_across_frames() {
+        let payload = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let slicer = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        for frame in slicer {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+
+        let mut buffer = [0u8; 512];
+        let transfer = assembler.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(transfer.payload, &payload);
+
+        // Corrupted crc byte in either of the last two frames
+        for corrupted in [1, 2] {
+            let mut result = Ok(ProcessOutcome::Accepted);
+            let tid = TransferId::new(corrupted as u8).unwrap();
+            for (i, mut frame) in Slicer::<8, 7>::new(&payload, tid).frames_owned().enumerate() {
+                if i == corrupted {
+                    frame.bytes[frame.len() - 2] ^= 0x01;
+                }
+                result = assembler.process_frame(id, &frame, ms(0));
+            }
+            assert_eq!(result, Err(ProcessError::BadCrc));
+        }
+    }
+
+    #[test]
+    fn check_fd_frames() {
+        let mut assembler = Assembler::<64, 63, 16, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+
+        // single frame padded from 10+t to 12 bytes, padding is delivered as part of the payload
+        let payload = [0xaa; 10];
+        let mut slicer = Slicer::<64, 63>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+        assembler.process_frame(id, &slicer.next().unwrap(), ms(0)).ok();
+        let transfer = assembler.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(transfer.payload.len(), 11);
+        assert_eq!(&transfer.payload[..10], &payload);
+        assert_eq!(transfer.payload[10], 0);
+
+        // 63+t 37+pad(8)+crc+t
+        let payload = [0x55; 100];
+        let slicer = Slicer::<64, 63>::new(&payload, TransferId::new(1).unwrap()).frames_owned();
+        for frame in slicer {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.counters.transfers_with_good_crc, 1);
+        let transfer = assembler.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(transfer.payload.len(), 108);
+        assert_eq!(&transfer.payload[..100], &payload);
+        assert_eq!(&transfer.payload[100..], &[0; 8]);
+
+        // 13 bytes is not a valid CAN FD frame length
+        let mut frame = [0u8; 13];
+        frame[12] = 0b1110_0010;
+        assembler.process_frame(id, &frame, ms(0)).ok();
+        assert!(assembler.pop(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn check_mixed_mtu() {
+        let mut assembler = Assembler::<64, 63, 16, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let classic_id = CanId::new_message_kind(NodeId::new(1).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let fd_id = CanId::new_message_kind(NodeId::new(2).unwrap(), SubjectId::new(7).unwrap(), false, Priority::High);
+        let classic_payload = [0x11; 20];
+        let fd_payload = [0x22; 100];
+        let mut classic = Slicer::<8, 7>::new(&classic_payload, TransferId::new(0).unwrap()).frames_owned();
+        let mut fd = Slicer::<64, 63>::new(&fd_payload, TransferId::new(0).unwrap()).frames_owned();
+        loop {
+            let classic_frame = classic.next();
+            let fd_frame = fd.next();
+            if classic_frame.is_none() && fd_frame.is_none() {
+                break;
+            }
+            classic_frame.map(|f| assembler.process_frame(classic_id, &f, ms(0)));
+            fd_frame.map(|f| assembler.process_frame(fd_id, &f, ms(0)));
+        }
+        assert_eq!(assembler.counters.transfers_with_good_crc, 2);
+
+        let mut buffer = [0u8; 512];
+        let transfer = assembler.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(transfer.source, NodeId::new(2).unwrap());
+        assert_eq!(&transfer.payload[..100], &fd_payload);
+        let transfer = assembler.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(transfer.source, NodeId::new(1).unwrap());
+        assert_eq!(transfer.payload, &classic_payload);
+    }
+
+    #[test]
+    fn check_duplicate_transfer() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+        let frame = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+
+        assembler.process_frame(id, &frame, ms(0)).ok();
+        assert!(assembler.pop(&mut buffer).unwrap().is_some());
+        assembler.process_frame(id, &frame, ms(100)).ok();
+        assert!(assembler.pop(&mut buffer).unwrap().is_none());
+        assert_eq!(assembler.counters.duplicate_transfers, 1);
+
+        let payload = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let frames = || Slicer::<8, 7>::new(&payload, TransferId::new(1).unwrap()).frames_owned();
+        for frame in frames() {
+            assembler.process_frame(id, &frame, ms(200)).ok();
+        }
+        assert!(assembler.pop(&mut buffer).unwrap().is_some());
+        for frame in frames() {
+            assembler.process_frame(id, &frame, ms(300)).ok();
+        }
+        assert!(assembler.pop(&mut buffer).unwrap().is_none());
+
+        // Same ID is accepted again after transfer-ID timeout
+        for frame in frames() {
+            assembler.process_frame(id, &frame, ms(200 + DEFAULT_TRANSFER_ID_TIMEOUT_MS + 1)).ok();
+        }
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload);
+    }
+
+    #[test]
+    fn check_foreign_frames_rejected() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let id_high = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::High);
+        let payload_a = [0xaa; 20];
+        let payload_b = [0xbb; 20];
+        let mut frames_a = Slicer::<8, 7>::new(&payload_a, TransferId::new(0).unwrap()).frames_owned();
+        let mut frames_b = Slicer::<8, 7>::new(&payload_b, TransferId::new(1).unwrap()).frames_owned();
+
+        assembler.process_frame(id, &frames_a.next().unwrap(), ms(0)).ok();
+        frames_b.next();
+        // Same toggle bit, but different transfer ID
+        assembler.process_frame(id, &frames_b.next().unwrap(), ms(0)).ok();
+        assert_eq!(assembler.counters.transfer_id_mismatch, 1);
+        // Right transfer ID and toggle bit, but different priority
+        let frame = frames_a.next().unwrap();
+        assembler.process_frame(id_high, &frame, ms(0)).ok();
+        assert_eq!(assembler.counters.priority_mismatch, 1);
+        assembler.process_frame(id, &frame, ms(0)).ok();
+        for frame in frames_a {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+
+        let mut buffer = [0u8; 512];
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload_a);
+    }
+
+    #[test]
+    fn check_restart_on_new_start() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+        let payload_a = [0xaa; 20];
+        let payload_b = [0xbb; 20];
+
+        // Single frame transfer interrupts a multi-frame one
+        let mut frames_a = Slicer::<8, 7>::new(&payload_a, TransferId::new(0).unwrap()).frames_owned();
+        assembler.process_frame(id, &frames_a.next().unwrap(), ms(0)).ok();
+        assembler.process_frame(id, &frames_a.next().unwrap(), ms(0)).ok();
+        let single = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(1).unwrap()).frames_owned().next().unwrap();
+        assembler.process_frame(id, &single, ms(0)).ok();
+        assert_eq!(assembler.storage.len(), 1);
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &[1, 2, 3]);
+
+        // Multi-frame transfer interrupts a multi-frame one
+        let mut frames_a = Slicer::<8, 7>::new(&payload_a, TransferId::new(2).unwrap()).frames_owned();
+        assembler.process_frame(id, &frames_a.next().unwrap(), ms(0)).ok();
+        for frame in Slicer::<8, 7>::new(&payload_b, TransferId::new(3).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.counters.interrupted_transfers, 2);
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload_b);
+    }
+
+    #[test]
+    fn check_duplicate_frames() {
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+        let payload = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20];
+
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert!(assembler.pop(&mut buffer).unwrap().is_none());
+
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        assembler.set_ignore_duplicate_frames(true);
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+            assert_eq!(assembler.process_frame(id, &frame, ms(0)), Ok(ProcessOutcome::DuplicateFrame));
+        }
+        // Including the copy of the last frame that arrives after transfer was moved to the ready queue
+        assert_eq!(assembler.counters.duplicate_frames, 4);
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload);
+
+        // Frames beyond extent are not stored, but copies of them are still recognized
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 7).unwrap();
+        let payload: [u8; 30] = core::array::from_fn(|i| i as u8);
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(1).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+            assert_eq!(assembler.process_frame(id, &frame, ms(0)), Ok(ProcessOutcome::DuplicateFrame));
+        }
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload[..7]);
+
+        // Same tail byte and length, but different data is not a copy
+        let frame = Slicer::<8, 7>::new(&payload, TransferId::new(2).unwrap()).frames_owned().next().unwrap();
+        let mut other = frame;
+        other.bytes[0] ^= 0xff;
+        assembler.process_frame(id, &frame, ms(0)).ok();
+        assert_ne!(assembler.process_frame(id, &other, ms(0)), Ok(ProcessOutcome::DuplicateFrame));
+        assert_eq!(assembler.process_frame(id, &other, ms(0)), Ok(ProcessOutcome::DuplicateFrame));
+        assert_eq!(assembler.validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_ready_queue() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 2, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+
+        // Session keeps receiving while previous transfers wait to be popped
+        for (i, payload) in [[1, 1, 1], [2, 2, 2], [3, 3, 3]].iter().enumerate() {
+            let frame = Slicer::<8, 7>::new(payload, TransferId::new(i as u8).unwrap()).frames_owned().next().unwrap();
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.counters.ready_queue_overflows, 1);
+        assert_eq!(assembler.storage.len(), 2);
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &[1, 1, 1]);
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &[2, 2, 2]);
+        assert!(assembler.pop(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn check_storage_reclaimed() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+        let payload = [0xaa; 30];
+
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.validate(), Ok(()));
+        assert!(assembler.pop(&mut buffer).unwrap().is_some());
+        assert_eq!(assembler.storage.len(), 0);
+
+        // Bad crc
+        for (i, mut frame) in Slicer::<8, 7>::new(&payload, TransferId::new(1).unwrap()).frames_owned().enumerate() {
+            if i == 1 {
+                frame.bytes[0] ^= 0xff;
+            }
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.counters.transfers_with_bad_crc, 1);
+        assert_eq!(assembler.storage.len(), 0);
+
+        // Interrupted by UAVCAN v0 frame
+        let mut frames = Slicer::<8, 7>::new(&payload, TransferId::new(2).unwrap()).frames_owned();
+        assembler.process_frame(id, &frames.next().unwrap(), ms(0)).ok();
+        assembler.process_frame(id, &[0, 0b1100_0010], ms(0)).ok();
+        assert_eq!(assembler.storage.len(), 0);
+        assert_eq!(assembler.validate(), Ok(()));
+
+        // Abandoned transfer followed by a new one
+        let mut frames = Slicer::<8, 7>::new(&payload, TransferId::new(3).unwrap()).frames_owned();
+        assembler.process_frame(id, &frames.next().unwrap(), ms(0)).ok();
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(4).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.validate(), Ok(()));
+        assert!(assembler.pop(&mut buffer).unwrap().is_some());
+        assert_eq!(assembler.storage.len(), 0);
+        assert_eq!(assembler.validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_process_outcome() {
+        let mut assembler = Assembler::<8, 7, 4, 2, 1, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+        let single = |tid| Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(tid).unwrap()).frames_owned().next().unwrap();
+
+        assert_eq!(assembler.process_frame(id, &single(0), ms(0)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.process_frame(id, &single(0), ms(1)), Ok(ProcessOutcome::DuplicateTransfer));
+        assert_eq!(assembler.process_frame(id, &single(1), ms(2)), Err(ProcessError::ReadyQueueFull));
+        assert!(assembler.pop(&mut buffer).unwrap().is_some());
+        assert_eq!(assembler.process_frame(id, &[], ms(2)), Err(ProcessError::MalformedFrame));
+
+        // Out of order frames
+        let payload = [0xaa; 20];
+        let mut frames = Slicer::<8, 7>::new(&payload, TransferId::new(2).unwrap()).frames_owned();
+        assert_eq!(assembler.process_frame(id, &frames.next().unwrap(), ms(2)), Ok(ProcessOutcome::Accepted));
+        let frame = frames.next().unwrap();
+        assert_eq!(assembler.process_frame(id, &frame, ms(2)), Ok(ProcessOutcome::Accepted));
+        assert_eq!(assembler.process_frame(id, &frame, ms(2)), Err(ProcessError::ToggleError));
+        assert_eq!(assembler.process_frame(id, &frames.next().unwrap(), ms(2)), Err(ProcessError::MissingStart));
+
+        // Bad crc
+        let mut result = Ok(ProcessOutcome::Accepted);
+        for (i, mut frame) in Slicer::<8, 7>::new(&payload, TransferId::new(3).unwrap()).frames_owned().enumerate() {
+            if i == 1 {
+                frame.bytes[0] ^= 0xff;
+            }
+            result = assembler.process_frame(id, &frame, ms(2));
+        }
+        assert_eq!(result, Err(ProcessError::BadCrc));
+
+        // 5 frames do not fit into 4 pieces
+        for frame in Slicer::<8, 7>::new(&[0x55; 30], TransferId::new(4).unwrap()).frames_owned() {
+            result = assembler.process_frame(id, &frame, ms(2));
+        }
+        assert_eq!(result, Err(ProcessError::OutOfStorage));
+        assert_eq!(assembler.storage.len(), 0);
+
+        let id4 = CanId::new_message_kind(NodeId::new(4).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let id5 = CanId::new_message_kind(NodeId::new(5).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        assert_eq!(assembler.process_frame(id4, &single(0), ms(2)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.process_frame(id5, &single(0), ms(2)), Err(ProcessError::SessionTableFull));
+
+        let counters = assembler.counters();
+        assert_eq!(counters.completed_transfers, 2);
+        // Transfer dropped because of the full ready queue is not counted
+        assert_eq!(counters.single_frame_transfers, 2);
+        assert_eq!(counters.duplicate_transfers, 1);
+        assert_eq!(counters.ready_queue_overflows, 1);
+        assert_eq!(counters.malformed_frames, 1);
+        assert_eq!(counters.toggle_errors, 1);
+        assert_eq!(counters.missing_start, 1);
+        assert_eq!(counters.transfers_with_bad_crc, 1);
+        assert_eq!(counters.out_of_storage, 1);
+        assert_eq!(counters.session_table_full, 1);
+    }
+
+    #[cfg(feature = "session-stats")]
+    #[test]
+    fn check_session_stats() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let good = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let bad = CanId::new_message_kind(NodeId::new(4).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let payload = [0xaa; 20];
+        for (i, mut frame) in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().enumerate() {
+            assembler.process_frame(good, &frame, ms(5)).ok();
+            if i == 1 {
+                frame.bytes[0] ^= 0xff;
+            }
+            assembler.process_frame(bad, &frame, ms(7)).ok();
+        }
+
+        let stats = assembler.session_stats(good.source_node_id, good.transfer_kind).unwrap();
+        assert_eq!(stats.accepted_frames, 4);
+        assert_eq!(stats.completed_transfers, 1);
+        assert_eq!(stats.rejected_frames, 0);
+        assert_eq!(stats.last_seen, ms(5));
+        let stats = assembler.session_stats(bad.source_node_id, bad.transfer_kind).unwrap();
+        assert_eq!(stats.accepted_frames, 3);
+        assert_eq!(stats.transfers_with_bad_crc, 1);
+        assert_eq!(stats.rejected_frames, 1);
+        assert_eq!(assembler.sessions_stats().count(), 2);
+    }
+
+    #[test]
+    fn check_subscriptions() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 2, 1000, 10>::new();
+        let mut buffer = [0u8; 512];
+        let subject = PortId::Subject(SubjectId::new(7).unwrap());
+        let request = PortId::Request(ServiceId::new(5).unwrap());
+        assembler.subscribe(subject, 10).unwrap();
+        assembler.subscribe(request, 512).unwrap();
+        assembler.set_local_node_id(NodeId::new(4));
+        assert_eq!(assembler.subscribe(PortId::Subject(SubjectId::new(8).unwrap()), 10), Err(Error::TooManySubscriptions));
+
+        let single = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        let message = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(8).unwrap(), false, Priority::Nominal);
+        assert_eq!(assembler.process_frame(message, &single, ms(0)), Err(ProcessError::NotSubscribed));
+        let response = CanId::new_service_kind(NodeId::new(3).unwrap(), NodeId::new(4).unwrap(), ServiceId::new(5).unwrap(), false, Priority::Nominal);
+        assert_eq!(assembler.process_frame(response, &single, ms(0)), Err(ProcessError::NotSubscribed));
+        let request_id = CanId::new_service_kind(NodeId::new(3).unwrap(), NodeId::new(4).unwrap(), ServiceId::new(5).unwrap(), true, Priority::Nominal);
+        assert_eq!(assembler.process_frame(request_id, &single, ms(0)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &[1, 2, 3]);
+        assert_eq!(assembler.transfers.len(), 1);
+
+        // Only the first 10 bytes are kept, crc of the whole transfer is still checked
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let payload: [u8; 30] = core::array::from_fn(|i| i as u8);
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+            assert!(assembler.storage.len() <= 2);
+        }
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload[..10]);
+
+        let mut result = Ok(ProcessOutcome::Accepted);
+        for (i, mut frame) in Slicer::<8, 7>::new(&payload, TransferId::new(1).unwrap()).frames_owned().enumerate() {
+            if i == 3 {
+                frame.bytes[0] ^= 0xff;
+            }
+            result = assembler.process_frame(id, &frame, ms(0));
+        }
+        assert_eq!(result, Err(ProcessError::BadCrc));
+        assert_eq!(assembler.storage.len(), 0);
+
+        // Crc split across the last two frames, both are not stored
+        assembler.subscribe(subject, 7).unwrap();
+        for frame in Slicer::<8, 7>::new(&payload[..13], TransferId::new(2).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload[..7]);
+
+        // First crc byte is stored, but not returned as payload
+        for (extent, transfer_id) in [(14, 3), (15, 4)] {
+            assembler.subscribe(subject, extent).unwrap();
+            for frame in Slicer::<8, 7>::new(&payload[..13], TransferId::new(transfer_id).unwrap()).frames_owned() {
+                assembler.process_frame(id, &frame, ms(0)).ok();
+            }
+            assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload[..13]);
+        }
+
+        // Single frame transfer is truncated as well
+        assembler.subscribe(subject, 2).unwrap();
+        let frame = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(5).unwrap()).frames_owned().next().unwrap();
+        assembler.process_frame(id, &frame, ms(0)).ok();
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &[1, 2]);
+
+        // Partially received transfers are dropped
+        let mut frames = Slicer::<8, 7>::new(&payload, TransferId::new(6).unwrap()).frames_owned();
+        assembler.process_frame(id, &frames.next().unwrap(), ms(0)).ok();
+        assert!(assembler.unsubscribe(subject));
+        assert!(!assembler.unsubscribe(subject));
+        assert_eq!(assembler.storage.len(), 0);
+        assert_eq!(assembler.process_frame(id, &frames.next().unwrap(), ms(0)), Err(ProcessError::NotSubscribed));
+        assert_eq!(assembler.validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_destination_node_id() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        let mut buffer = [0u8; 512];
+        assembler.subscribe(PortId::Request(ServiceId::new(5).unwrap()), 512).unwrap();
+        let frame = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        let to_node = |destination| CanId::new_service_kind(NodeId::new(3).unwrap(), NodeId::new(destination).unwrap(), ServiceId::new(5).unwrap(), true, Priority::Nominal);
+
+        // Anonymous node can't receive services
+        assert_eq!(assembler.process_frame(to_node(4), &frame, ms(0)), Err(ProcessError::NotForThisNode));
+        assembler.set_local_node_id(NodeId::new(4));
+        assert_eq!(assembler.process_frame(to_node(5), &frame, ms(0)), Err(ProcessError::NotForThisNode));
+        assert_eq!(assembler.transfers.len(), 0);
+        assert_eq!(assembler.process_frame(to_node(4), &frame, ms(0)), Ok(ProcessOutcome::TransferComplete));
+        assert!(assembler.pop(&mut buffer).unwrap().is_some());
+
+        assembler.set_promiscuous(true);
+        assert_eq!(assembler.process_frame(to_node(5), &frame, ms(0)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.counters().not_for_this_node, 2);
+    }
+
+    #[test]
+    fn check_pop_ref() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let payload: [u8; 30] = core::array::from_fn(|i| i as u8);
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+
+        {
+            let transfer = assembler.pop_ref().unwrap();
+            assert_eq!(transfer.source, NodeId::new(3).unwrap());
+            assert_eq!(transfer.len(), 30);
+            let mut chunks = transfer.chunks();
+            assert_eq!(chunks.next(), Some(&payload[..7]));
+            assert_eq!(chunks.last(), Some(&payload[28..]));
+            let mut buffer = [0u8; 29];
+            assert_eq!(transfer.copy_to(&mut buffer), Err(Error::BufferTooSmall));
+            let mut buffer = [0u8; 32];
+            assert_eq!(transfer.copy_to(&mut buffer), Ok(30));
+            assert_eq!(&buffer[..30], &payload);
+        }
+        assert_eq!(assembler.storage.len(), 0);
+        assert!(assembler.pop_ref().is_none());
+
+        // Transfer stays in the queue if the buffer is too small
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(1).unwrap()).frames_owned() {
+            assembler.process_frame(id, &frame, ms(0)).ok();
+        }
+        let mut buffer = [0u8; 29];
+        assert_eq!(assembler.pop(&mut buffer).err(), Some(Error::BufferTooSmall));
+        assert_eq!(assembler.ready.len(), 1);
+        let mut buffer = [0u8; 30];
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &payload);
+        assert_eq!(assembler.storage.len(), 0);
+    }
+
+    #[test]
+    fn check_eviction() {
+        let mut assembler = Assembler::<8, 7, 4, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let mut buffer = [0u8; 512];
+        let low_old = CanId::new_message_kind(NodeId::new(1).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Low);
+        let low_new = CanId::new_message_kind(NodeId::new(2).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Low);
+        let nominal = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let high = CanId::new_message_kind(NodeId::new(4).unwrap(), SubjectId::new(7).unwrap(), false, Priority::High);
+        let payload = [0xaa; 30];
+        let mut frames_old = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+        let mut frames_new = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+        let mut frames_nominal = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+        assembler.process_frame(low_old, &frames_old.next().unwrap(), ms(0)).ok();
+        assembler.process_frame(low_new, &frames_new.next().unwrap(), ms(1)).ok();
+        assembler.process_frame(nominal, &frames_nominal.next().unwrap(), ms(2)).ok();
+        assembler.process_frame(nominal, &frames_nominal.next().unwrap(), ms(2)).ok();
+        assert_eq!(assembler.storage.len(), 4);
+
+        // Same priority, nothing to evict
+        let low = CanId::new_message_kind(NodeId::new(5).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Low);
+        let frame = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        assert_eq!(assembler.process_frame(low, &frame, ms(3)), Err(ProcessError::OutOfStorage));
+        assert_eq!(assembler.counters.evicted_transfers, 0);
+
+        // Oldest of the lowest priority transfers is evicted first
+        let mut frames = Slicer::<8, 7>::new(&[0x55; 10], TransferId::new(0).unwrap()).frames_owned();
+        assert!(assembler.process_frame(high, &frames.next().unwrap(), ms(4)).is_ok());
+        assert_eq!(assembler.counters.evicted_transfers, 1);
+        assert_eq!(assembler.process_frame(low_old, &frames_old.next().unwrap(), ms(4)), Err(ProcessError::MissingStart));
+        assert_eq!(assembler.process_frame(high, &frames.next().unwrap(), ms(4)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.counters.evicted_transfers, 2);
+        assert_eq!(assembler.pop(&mut buffer).unwrap().unwrap().payload, &[0x55; 10]);
+        assert_eq!(assembler.process_frame(low_new, &frames_new.next().unwrap(), ms(5)), Err(ProcessError::MissingStart));
+        assert_eq!(assembler.process_frame(nominal, &frames_nominal.next().unwrap(), ms(5)), Ok(ProcessOutcome::Accepted));
+        assert_eq!(assembler.validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_transfer_metadata() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let payload = [0xaa; 20];
+        for (i, frame) in Slicer::<8, 7>::new(&payload, TransferId::new(5).unwrap()).frames_owned().enumerate() {
+            assembler.process_frame(id, &frame, ms(10 + i as u32 * 10)).ok();
+        }
+        let transfer = assembler.pop_ref().unwrap();
+        assert_eq!(transfer.transfer_id, TransferId::new(5).unwrap());
+        assert_eq!(transfer.first_frame_timestamp, ms(10));
+        assert_eq!(transfer.last_frame_timestamp, ms(40));
+        assert_eq!(transfer.frame_count, 4);
+        assert_eq!(transfer.iface, 0);
+        drop(transfer);
+
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(6).unwrap()).frames_owned() {
+            assembler.process_frame_from(1, id, &frame, ms(50)).ok();
+        }
+        assert_eq!(assembler.pop_ref().unwrap().iface, 1);
+    }
+
+    #[test]
+    fn check_redundant_interfaces() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let mut buffer = [0u8; 512];
+        let payload = [0xaa; 20];
+
+        // Interface 0 lags behind by one frame
+        let frames: std::vec::Vec<_> = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().collect();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(assembler.process_frame_from(1, id, &frames[0], ms(0)), Ok(ProcessOutcome::Accepted));
+        for i in 1..3 {
+            assert_eq!(assembler.process_frame_from(0, id, &frames[i - 1], ms(0)), Err(ProcessError::InterfaceMismatch));
+            assert_eq!(assembler.process_frame_from(1, id, &frames[i], ms(0)), Ok(ProcessOutcome::Accepted));
+        }
+        assert_eq!(assembler.process_frame_from(0, id, &frames[2], ms(0)), Err(ProcessError::InterfaceMismatch));
+        assert_eq!(assembler.process_frame_from(1, id, &frames[3], ms(0)), Ok(ProcessOutcome::TransferComplete));
+        // Last frame from the lagging interface arrives after the transfer was completed
+        assert_eq!(assembler.process_frame_from(0, id, &frames[3], ms(0)), Err(ProcessError::InterfaceMismatch));
+        assert_eq!(assembler.process_frame_from(1, id, &frames[3], ms(0)), Ok(ProcessOutcome::DuplicateTransfer));
+        // Copy of the whole transfer from interface 0 would be a duplicate
+        assert_eq!(assembler.process_frame_from(0, id, &frames[0], ms(0)), Ok(ProcessOutcome::DuplicateTransfer));
+        assert_eq!(assembler.counters.dropped_frames, 0);
+        assert_eq!(assembler.counters.missing_start, 0);
+        let transfer = assembler.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(transfer.iface, 1);
+        assert_eq!(transfer.payload, &payload);
+        assert!(assembler.pop(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn check_timer_wraparound() {
+        let mut assembler = Assembler::<8, 7, 4, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let a = CanId::new_message_kind(NodeId::new(1).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let b = CanId::new_message_kind(NodeId::new(2).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let payload = [0xaa; 30];
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().take(4) {
+            assembler.process_frame(a, &frame, ms(u32::MAX - 2)).ok();
+        }
+        assert_eq!(assembler.storage.len(), 4);
+
+        // Frame with an older timestamp doesn't make transfer outdated
+        let frame = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        assert_eq!(assembler.process_frame(b, &frame, ms(u32::MAX - 5)), Err(ProcessError::OutOfStorage));
+        assert_eq!(assembler.process_frame(b, &frame, ms(5)), Err(ProcessError::OutOfStorage));
+        // Lifetime is over after timer wrapped around
+        assert_eq!(assembler.process_frame(b, &frame, ms(20)), Ok(ProcessOutcome::Accepted));
+        assert_eq!(assembler.storage.len(), 1);
+
+        let single = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(1).unwrap()).frames_owned().next().unwrap();
+        assert_eq!(assembler.process_frame(a, &single, ms(u32::MAX - 1)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.process_frame(a, &single, ms(100)), Ok(ProcessOutcome::DuplicateTransfer));
+    }
+
+    #[test]
+    fn check_expire() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        assembler.set_transfer_id_timeout(TimerDurationU32::millis(100));
+        let a = CanId::new_message_kind(NodeId::new(1).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let b = CanId::new_message_kind(NodeId::new(2).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let payload = [0xaa; 30];
+        let mut frames = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+        assembler.process_frame(a, &frames.next().unwrap(), ms(0)).ok();
+        assembler.process_frame(a, &frames.next().unwrap(), ms(0)).ok();
+        let single = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        assembler.process_frame(b, &single, ms(5)).ok();
+
+        assert_eq!(assembler.expire(ms(10)), ExpireStats::default());
+        // Transfer lifetime is over, but session is kept to detect duplicates
+        assert_eq!(assembler.expire(ms(11)), ExpireStats { sessions: 0, transfers: 1, ready_transfers: 0, pieces: 2 });
+        assert_eq!(assembler.storage.len(), 1);
+        assert_eq!(assembler.expire(ms(101)), ExpireStats { sessions: 1, transfers: 0, ready_transfers: 0, pieces: 0 });
+        assert_eq!(assembler.process_frame(b, &single, ms(102)), Ok(ProcessOutcome::DuplicateTransfer));
+        assert_eq!(assembler.expire(ms(300)), ExpireStats { sessions: 1, transfers: 0, ready_transfers: 0, pieces: 0 });
+        assert_eq!(assembler.transfers.len(), 0);
+
+        // Not popped transfer
+        assert_eq!(assembler.ready.len(), 1);
+        assembler.set_ready_transfer_timeout(Some(TimerDurationU32::millis(1000)));
+        assert_eq!(assembler.expire(ms(1005)), ExpireStats::default());
+        assert_eq!(assembler.expire(ms(1006)), ExpireStats { sessions: 0, transfers: 0, ready_transfers: 1, pieces: 1 });
+        assert_eq!(assembler.storage.len(), 0);
+        assert_eq!(assembler.counters.expired_transfers, 2);
+        assert_eq!(assembler.validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_next_deadline() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        assembler.set_transfer_id_timeout(TimerDurationU32::millis(100));
+        assert_eq!(assembler.next_deadline(ms(0)), None);
+        let a = CanId::new_message_kind(NodeId::new(1).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let b = CanId::new_message_kind(NodeId::new(2).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let single = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        assembler.process_frame(b, &single, ms(0)).ok();
+        assert_eq!(assembler.next_deadline(ms(0)), Some(ms(101)));
+        let payload = [0xaa; 30];
+        let first = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        assembler.process_frame(a, &first, ms(50)).ok();
+        assert_eq!(assembler.next_deadline(ms(50)), Some(ms(61)));
+        assert_eq!(assembler.expire(ms(60)).transfers, 0);
+        assert_eq!(assembler.expire(ms(61)).transfers, 1);
+        assert_eq!(assembler.next_deadline(ms(61)), Some(ms(101)));
+        // Already past the deadline
+        assert_eq!(assembler.next_deadline(ms(120)), Some(ms(120)));
+        assembler.expire(ms(120));
+        assert_eq!(assembler.next_deadline(ms(120)), Some(ms(151)));
+        assembler.expire(ms(151));
+        assert_eq!(assembler.next_deadline(ms(151)), None);
+        assembler.set_ready_transfer_timeout(Some(TimerDurationU32::millis(1000)));
+        assert_eq!(assembler.next_deadline(ms(151)), Some(ms(1001)));
+
+        // Deadline crosses timer overflow
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let start = TimerInstantU32::<1000>::from_ticks(u32::MAX - 5);
+        assembler.process_frame(a, &first, start).ok();
+        assert_eq!(assembler.next_deadline(start), Some(TimerInstantU32::from_ticks(5)));
+    }
+
+    #[test]
+    fn check_anonymous_transfers() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 8, 4, 1000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let payload = [1, 2, 3];
+        let id = CanId::new_anonymous_message_kind(SubjectId::new(7).unwrap(), Priority::Nominal, &payload);
+        let frame = Slicer::<8, 7>::new_anonymous(&payload, TransferId::new(0).unwrap()).unwrap().frames_owned().next().unwrap();
+        assert_eq!(assembler.process_frame(id, &frame, ms(0)), Ok(ProcessOutcome::TransferComplete));
+        // Not deduplicated, different anonymous nodes may send the same payload with the same pseudo ID
+        assert_eq!(assembler.process_frame(id, &frame, ms(0)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.transfers.len(), 0);
+        let mut buf = [0u8; 8];
+        let transfer = assembler.pop(&mut buf).unwrap().unwrap();
+        assert_eq!(transfer.payload, &payload);
+        assert_eq!(transfer.source, id.source_node_id);
+
+        let payload = [0xaa; 20];
+        assert_eq!(Slicer::<8, 7>::new_anonymous(&payload, TransferId::new(0).unwrap()).err(), Some(Error::AnonymousPayloadTooLong));
+        let id = CanId::new_anonymous_message_kind(SubjectId::new(7).unwrap(), Priority::Nominal, &payload);
+        for frame in Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned() {
+            assert_eq!(assembler.process_frame(id, &frame, ms(0)), Err(ProcessError::AnonymousMultiFrame));
+        }
+        assert_eq!(assembler.counters.anonymous_multi_frame, 4);
+        // UAVCAN v0 single frame
+        assert_eq!(assembler.process_frame(id, &[1, 2, 3, 0b1100_0000], ms(0)), Err(ProcessError::UnsupportedVersion));
+        assert_eq!(assembler.transfers.len(), 0);
+        assert_eq!(assembler.storage.len(), 1);
+        assert_eq!(assembler.validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_long_idle_session() {
+        let mut assembler = Assembler::<8, 7, 128, 8, 16, 4, 1_000_000, 10>::new();
+        assembler.subscribe(PortId::Subject(SubjectId::new(7).unwrap()), 512).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(3).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let other = CanId::new_message_kind(NodeId::new(4).unwrap(), SubjectId::new(7).unwrap(), false, Priority::Nominal);
+        let frame = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(0).unwrap()).frames_owned().next().unwrap();
+        let at = |t: u32| TimerInstantU32::<1_000_000>::from_ticks(t);
+        assert_eq!(assembler.process_frame(id, &frame, at(0)), Ok(ProcessOutcome::TransferComplete));
+        assert_eq!(assembler.process_frame(id, &frame, at(1000)), Ok(ProcessOutcome::DuplicateTransfer));
+
+        // Session is quiet for more than half of the timer range, while others are not
+        for i in 1..=8u32 {
+            let frame = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(i as u8).unwrap()).frames_owned().next().unwrap();
+            assembler.process_frame(other, &frame, at(i << 28)).ok();
+        }
+        assert_eq!(assembler.transfers.len(), 1);
+        assert_eq!(assembler.process_frame(id, &frame, at((1 << 31) + 1000)), Ok(ProcessOutcome::TransferComplete));
+
+        // Same with expire() being called periodically
+        for i in 9..=16u32 {
+            assembler.expire(at(i << 28));
+        }
+        assert_eq!(assembler.transfers.len(), 0);
     }
 }
\ No newline at end of file
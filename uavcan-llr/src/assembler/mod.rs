@@ -1,5 +1,5 @@
 pub mod assembler;
-pub use assembler::Assembler;
+pub use assembler::{Assembler, ProcessOutcome, ProcessError, ReadyTransfer, RefReadyTransfer, ExpireStats};
 
 mod storage;
 mod transfer;
@@ -8,7 +8,7 @@ mod transfer;
 // TODO: Check if this actually produces smaller memory footprint.
 pub(crate) mod types {
     /// Used to sort incoming transfer by time of arrival, so that equal priority transfer are in fifo order
-    /// Must be able to hold 2 * MAX_TRANSFERS
+    /// Must be able to hold 2 * MAX_READY
     pub(crate) type TransferSeq = i16;
     /// Used to index into frame data
     pub(crate) type PieceByteIdx = u8;
@@ -1,9 +1,12 @@
 use core::fmt::{Formatter, Display, Result as FmtResult};
 use super::types::*;
+#[cfg(any(test, debug_assertions))]
+use super::assembler::ValidationError;
 
 #[derive(Copy, Clone)]
 pub(crate)  enum Piece<const N: usize> {
-    Empty,
+    /// next empty piece
+    Empty(Option<PieceIdx>),
     /// bytes, next
     Filled([u8; N], PieceIdx),
 }
@@ -11,30 +14,37 @@ pub(crate)  enum Piece<const N: usize> {
 pub(crate)  struct PiecesStorage<const N: usize, const MAX_PIECES: usize> {
     items: [Piece<N>; MAX_PIECES],
     used: PieceIdx,
+    /// Head of the list of empty pieces, linked through Piece::Empty
+    free: Option<PieceIdx>,
 }
 impl<const N: usize, const MAX_PIECES: usize> PiecesStorage<N, MAX_PIECES> {
     pub(crate) fn new() -> Self {
+        let mut items = [Piece::Empty(None); MAX_PIECES];
+        for (i, item) in items.iter_mut().enumerate().take(MAX_PIECES.saturating_sub(1)) {
+            *item = Piece::Empty(Some(i as PieceIdx + 1));
+        }
         PiecesStorage {
-            items: [Piece::Empty; MAX_PIECES],
+            items,
             used: 0,
+            free: if MAX_PIECES > 0 { Some(0) } else { None },
         }
     }
 
     pub(crate) fn push(&mut self, data: [u8; N]) -> Result<PieceIdx, [u8; N]> {
-        let empty_slot_idx = self.find_empty_slot().ok_or(data)?;
+        let empty_slot_idx = self.take_empty_slot().ok_or(data)?;
         self.items[empty_slot_idx as usize] = Piece::Filled(data, empty_slot_idx);
-        self.used += 1;
         Ok(empty_slot_idx)
     }
 
     pub(crate) fn push_after(&mut self, data: [u8; N], after_piece_idx: PieceIdx) -> Result<PieceIdx, [u8; N]> {
-        let empty_slot_idx = self.find_empty_slot().ok_or(data)?;
-        self.items[after_piece_idx as usize] = match self.items[after_piece_idx as usize] {
-            Piece::Empty => return Err(data),
-            Piece::Filled(data, _) => Piece::Filled(data, empty_slot_idx),
-        };
+        if let Piece::Empty(_) = self.items[after_piece_idx as usize] {
+            return Err(data);
+        }
+        let empty_slot_idx = self.take_empty_slot().ok_or(data)?;
+        if let Piece::Filled(_, next) = &mut self.items[after_piece_idx as usize] {
+            *next = empty_slot_idx;
+        }
         self.items[empty_slot_idx as usize] = Piece::Filled(data, empty_slot_idx);
-        self.used += 1;
         Ok(empty_slot_idx)
     }
 
@@ -45,18 +55,15 @@ impl<const N: usize, const MAX_PIECES: usize> PiecesStorage<N, MAX_PIECES> {
         }
     }
 
-    pub(crate) fn find_empty_slot(&self) -> Option<PieceIdx> {
-        for (i, slot) in self.items.iter().enumerate() {
-            match slot {
-                Piece::Empty => {
-                    return Some(i as PieceIdx);
-                }
-                Piece::Filled(_, _) => {
-                    continue;
-                }
-            }
-        }
-        None
+    /// Remove first piece from the list of empty ones, caller must fill it
+    fn take_empty_slot(&mut self) -> Option<PieceIdx> {
+        let idx = self.free?;
+        self.free = match self.items[idx as usize] {
+            Piece::Empty(next) => next,
+            Piece::Filled(_, _) => unreachable!(),
+        };
+        self.used += 1;
+        Some(idx)
     }
 
     /// Remove all pieces starting from first_piece_idx and return an amount of items removed
@@ -64,18 +71,19 @@ impl<const N: usize, const MAX_PIECES: usize> PiecesStorage<N, MAX_PIECES> {
         let mut idx = first_piece_idx;
         let mut removed = 0;
         loop {
-            self.items[idx as usize] = match &self.items[idx as usize] {
-                Piece::Empty => {
+            match self.items[idx as usize] {
+                Piece::Empty(_) => {
                     break;
                 }
                 Piece::Filled(_, next) => {
+                    self.items[idx as usize] = Piece::Empty(self.free);
+                    self.free = Some(idx);
                     self.used -= 1;
                     removed += 1;
-                    if idx == *next {
+                    if idx == next {
                         break;
                     }
-                    idx = *next;
-                    Piece::Empty
+                    idx = next;
                 }
             }
         }
@@ -85,12 +93,69 @@ impl<const N: usize, const MAX_PIECES: usize> PiecesStorage<N, MAX_PIECES> {
     pub(crate) fn len(&self) -> usize {
         self.used as usize
     }
+
+    /// Check that every chain (first piece index, expected last piece index) ends properly, chains do not
+    /// loop or overlap and that there are no filled pieces not belonging to any chain.
+    #[cfg(any(test, debug_assertions))]
+    pub(crate) fn validate<I: Iterator<Item = (PieceIdx, Option<PieceIdx>)>>(&self, chains: I) -> Result<(), ValidationError> {
+        let mut visited = [false; MAX_PIECES];
+        for (first_piece_idx, last_piece_idx) in chains {
+            let mut idx = first_piece_idx;
+            loop {
+                if visited[idx as usize] {
+                    return Err(ValidationError::CyclicChain);
+                }
+                visited[idx as usize] = true;
+                match self.items[idx as usize] {
+                    Piece::Empty(_) => return Err(ValidationError::BrokenChain),
+                    Piece::Filled(_, next) => {
+                        if idx == next {
+                            if last_piece_idx.map(|last| last != idx).unwrap_or(false) {
+                                return Err(ValidationError::BrokenChain);
+                            }
+                            break;
+                        }
+                        idx = next;
+                    }
+                }
+            }
+        }
+        let mut filled = 0;
+        for (item, visited) in self.items.iter().zip(visited.iter()) {
+            if let Piece::Filled(_, _) = item {
+                if !visited {
+                    return Err(ValidationError::OrphanedPiece);
+                }
+                filled += 1;
+            }
+        }
+        if filled != self.used as usize {
+            return Err(ValidationError::WrongUsedCount);
+        }
+        let mut empty = 0;
+        let mut idx = self.free;
+        while let Some(i) = idx {
+            if visited[i as usize] {
+                return Err(ValidationError::BrokenFreeList);
+            }
+            visited[i as usize] = true;
+            idx = match self.items[i as usize] {
+                Piece::Empty(next) => next,
+                Piece::Filled(_, _) => return Err(ValidationError::BrokenFreeList),
+            };
+            empty += 1;
+        }
+        if empty + filled != MAX_PIECES {
+            return Err(ValidationError::BrokenFreeList);
+        }
+        Ok(())
+    }
 }
 impl<const N: usize, const MAX_PIECES: usize> Display for PiecesStorage<N, MAX_PIECES> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         for (i, slot) in self.items.iter().enumerate() {
             match slot {
-                Piece::Empty => {
+                Piece::Empty(_) => {
                     write!(f, "{}: []\t", i).ok();
                 }
                 Piece::Filled(data, next) => {
@@ -115,7 +180,7 @@ impl<'a, const N: usize, const MAX_PIECES: usize> Iterator for PiecesIter<'a, N,
     fn next(&mut self) -> Option<Self::Item> {
         let (out, next_idx) = match self.idx {
             Some(idx) => match &self.items[idx as usize] {
-                Piece::Empty => (None, None),
+                Piece::Empty(_) => (None, None),
                 Piece::Filled(data, next_idx) => {
                     if idx == *next_idx {
                         (Some((&data[..], true)), None)
@@ -129,4 +194,32 @@ impl<'a, const N: usize, const MAX_PIECES: usize> Iterator for PiecesIter<'a, N,
         self.idx = next_idx;
         out
     }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::assembler::storage::PiecesStorage;
+
+    #[test]
+    fn check_free_list() {
+        let mut storage = PiecesStorage::<1, 4>::new();
+        let a = storage.push([0]).unwrap();
+        let a1 = storage.push_after([1], a).unwrap();
+        let b = storage.push([2]).unwrap();
+        storage.push_after([3], a1).unwrap();
+        assert_eq!(storage.push([4]), Err([4]));
+        assert_eq!(storage.len(), 4);
+        assert_eq!(storage.validate([(a, None), (b, None)].iter().cloned()), Ok(()));
+
+        assert_eq!(storage.remove_all(a), 3);
+        assert_eq!(storage.validate([(b, None)].iter().cloned()), Ok(()));
+        // Chain can't be continued after a removed piece
+        assert_eq!(storage.push_after([5], a), Err([5]));
+        for i in 0..3 {
+            storage.push([i]).unwrap();
+        }
+        assert_eq!(storage.push([4]), Err([4]));
+        assert_eq!(storage.traverse(b).next(), Some((&[2][..], true)));
+    }
 }
\ No newline at end of file
@@ -3,18 +3,41 @@ use core::fmt::{Formatter, Display, Result as FmtResult};
 use crate::tailbyte::{TailByte, Kind};
 use hash32_derive::Hash32;
 use super::types::*;
+use super::storage::PiecesStorage;
+use super::assembler::ProcessError;
+use crate::crc::TransferCrc;
+#[cfg(feature = "session-stats")]
+use super::assembler::RawSessionStats;
 
 
+/// Multi-frame transfers are sent with at least Classic CAN MTU
+pub(crate) const MIN_MTU: usize = 8;
+
+/// Timer ticks passed since earlier, zero if earlier is actually later than now.
+/// Timer is allowed to wrap around, measured intervals must be shorter than half of its range.
+pub(crate) fn elapsed(now: u32, earlier: u32) -> u32 {
+    let elapsed = now.wrapping_sub(earlier);
+    if elapsed > u32::MAX / 2 {
+        0
+    } else {
+        elapsed
+    }
+}
+
 #[derive(Copy, Clone)]
-pub(crate) struct TransferMachine<const MTU: usize> {
+pub(crate) struct TransferMachine {
     pub(crate) state: State,
     pub(crate) transfer_id: Option<TransferId>,
+    /// MTU of the sender, taken from the length of the first frame of a multi-frame transfer,
+    /// so that Classic CAN and CAN FD nodes can be served at the same time
+    pub(crate) mtu: PieceByteIdx,
 }
-impl<const MTU: usize> Display for TransferMachine<MTU> {
+impl Display for TransferMachine {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        write!(f, "TM({:?}, Tid:{:?}",
+        write!(f, "TM({:?}, Tid:{:?}, MTU:{}",
                self.state,
                self.transfer_id,
+               self.mtu,
         )
     }
 }
@@ -28,6 +51,7 @@ pub(crate) enum State {
     Failure,
 }
 
+/// Frame length relative to the MTU of the sender
 pub(crate) enum PayloadKind {
     Empty,
     LessThanMTU,
@@ -38,63 +62,87 @@ pub(crate) enum PayloadKind {
 #[derive(Eq, PartialEq, Debug)]
 pub(crate) enum TransferMachineOutput {
     /// Ignore incoming frame data
-    Ignore,
+    Ignore(ProcessError),
     /// Save incoming frame data into storage
     Push,
     /// Check CRC of the whole transfer, push on success or fail and wipe storage otherwise
     CheckCrcAndPush,
     /// Ignore incoming frame data and wipe storage from all previous pieces received
-    Drop,
+    Drop(ProcessError),
+    /// Wipe storage from all previous pieces received and save incoming frame data as the first piece
+    Restart,
 }
 
-impl<const MTU: usize> TransferMachine<MTU>
+impl TransferMachine
 {
     const fn reset() -> Self {
         TransferMachine {
             state: State::Empty,
             transfer_id: None,
+            mtu: 0,
         }
     }
 
+    /// frame_len is the length of a whole frame with tail byte, tail_byte is None if frame is invalid
     pub(crate) fn advance(
         &mut self,
-        payload_kind: PayloadKind,
+        frame_len: usize,
         tail_byte: Option<TailByte>,
     ) -> TransferMachineOutput {
         use State::*;
         use TransferMachineOutput::*;
 
+        let payload_kind = match frame_len {
+            1 => PayloadKind::Empty,
+            len if len < self.mtu as usize => PayloadKind::LessThanMTU,
+            len if len == self.mtu as usize => PayloadKind::ExactlyMTU,
+            _ => PayloadKind::Invalid,
+        };
+
         // println!("tail: {:?}, state: {:?}", tail_byte, self.state);
         let (next_state, output) = match (tail_byte, self.state) {
             // Got a frame without the tail byte
             // No need to remove pieces from storage
-            (None, Empty | Failure) => (Failure, Ignore),
+            (None, Empty | Failure) => (Failure, Ignore(ProcessError::MalformedFrame)),
             // Need to remove pieces from storage
             // TODO: Ignore empty frames in the middle of a transfer?
-            (None, AssemblingT1 | AssemblingT0) => (Failure, Drop),
+            (None, AssemblingT1 | AssemblingT0) => (Failure, Drop(ProcessError::MalformedFrame)),
             // Ignore and do not destroy valid transfer
-            (None, Done) => (Done, Ignore),
+            (None, Done) => (Done, Ignore(ProcessError::MalformedFrame)),
             (Some(tail_byte), state) => {
-                self.transfer_id = Some(tail_byte.id);
+                if tail_byte.is_start() {
+                    self.transfer_id = Some(tail_byte.id);
+                }
                 match (tail_byte.kind, state) {
                     // Single frame transfer from "idle" states, ok
                     (Kind::SingleFrame, Empty | Done | Failure) => (Done, Push),
 
-                    // Single frame transfer in the middle of a multi-frame one, error
-                    // TODO: Accept single frame transfer in the middle of an ongoing one?
-                    (Kind::SingleFrame, AssemblingT1 | AssemblingT0) => (Failure, Drop),
+                    // Single frame transfer in the middle of a multi-frame one, sender gave up on the
+                    // previous transfer, drop it and accept the new one
+                    (Kind::SingleFrame, AssemblingT1 | AssemblingT0) => (Done, Restart),
 
                     // Start of a multi-frame transfer from "idle" states, ok
-                    (Kind::MultiFrame, Empty | Done | Failure) => (AssemblingT1, Push),
+                    // First frame is always full and defines the MTU for the rest of the transfer
+                    (Kind::MultiFrame, Empty | Done | Failure) => if frame_len >= MIN_MTU {
+                        self.mtu = frame_len as PieceByteIdx;
+                        (AssemblingT1, Push)
+                    } else {
+                        (Failure, Ignore(ProcessError::UnexpectedLength))
+                    },
 
-                    // Repeated start in the middle of a multi-frame transfer, error
-                    // TODO: Accept multi frame transfer in the middle of an ongoing one?
-                    (Kind::MultiFrame, AssemblingT1 | AssemblingT0) => (Failure, Drop),
+                    // Start of a new transfer in the middle of a multi-frame one, sender gave up on the
+                    // previous transfer, drop it and start assembling the new one
+                    (Kind::MultiFrame, AssemblingT1 | AssemblingT0) => if frame_len >= MIN_MTU {
+                        self.mtu = frame_len as PieceByteIdx;
+                        (AssemblingT1, Restart)
+                    } else {
+                        (Failure, Drop(ProcessError::UnexpectedLength))
+                    },
 
                     // Frame with toggle=0 after previous one with toggle=1, ok
                     (Kind::MiddleT0, AssemblingT1) => match payload_kind {
                         PayloadKind::ExactlyMTU => (AssemblingT0, Push),
-                        _ => (Failure, Drop),
+                        _ => (Failure, Drop(ProcessError::UnexpectedLength)),
                     },
 
                     // Last frame with toggle=0 after previous one with toggle=1, ok
@@ -102,19 +150,19 @@ impl<const MTU: usize> TransferMachine<MTU>
                         PayloadKind::LessThanMTU | PayloadKind::ExactlyMTU => {
                             (Done, CheckCrcAndPush)
                         }
-                        _ => (Failure, Drop),
+                        _ => (Failure, Drop(ProcessError::UnexpectedLength)),
                     },
 
                     // Frame with toggle=0 after previous one with toggle=0, reorder error
-                    (Kind::MiddleT0 | Kind::EndT0, AssemblingT0) => (Failure, Drop),
+                    (Kind::MiddleT0 | Kind::EndT0, AssemblingT0) => (Failure, Drop(ProcessError::ToggleError)),
 
                     // Frame that doesn't belong to an ongoing multi-frame transfer, error
-                    (Kind::MiddleT0 | Kind::EndT0, _) => (Failure, Drop),
+                    (Kind::MiddleT0 | Kind::EndT0, _) => (Failure, Drop(ProcessError::MissingStart)),
 
                     // Frame with toggle=1 after previous one with toggle=0, ok
                     (Kind::MiddleT1, AssemblingT0) => match payload_kind {
                         PayloadKind::ExactlyMTU => (AssemblingT1, Push),
-                        _ => (Failure, Drop),
+                        _ => (Failure, Drop(ProcessError::UnexpectedLength)),
                     },
 
                     // Last frame with toggle=1 after previous one with toggle=0, ok
@@ -122,17 +170,20 @@ impl<const MTU: usize> TransferMachine<MTU>
                         PayloadKind::LessThanMTU | PayloadKind::ExactlyMTU => {
                             (Done, CheckCrcAndPush)
                         }
-                        _ => (Failure, Drop),
+                        _ => (Failure, Drop(ProcessError::UnexpectedLength)),
                     },
 
                     // Frame with toggle=1 after previous one with toggle=1, reorder error
-                    (Kind::MiddleT1 | Kind::EndT1, AssemblingT1) => (Failure, Drop),
+                    (Kind::MiddleT1 | Kind::EndT1, AssemblingT1) => (Failure, Drop(ProcessError::ToggleError)),
 
                     // Frame that doesn't belong to an ongoing multi-frame transfer, error
-                    (Kind::MiddleT1 | Kind::EndT1, _) => (Failure, Drop),
+                    (Kind::MiddleT1 | Kind::EndT1, _) => (Failure, Drop(ProcessError::MissingStart)),
 
                     // UAVCAN Version 0 tail byte, error
-                    (Kind::SingleFrameV0 | Kind::MultiFrameV0, _) => (Failure, Ignore),
+                    (Kind::SingleFrameV0 | Kind::MultiFrameV0, AssemblingT1 | AssemblingT0) => {
+                        (Failure, Drop(ProcessError::UnsupportedVersion))
+                    }
+                    (Kind::SingleFrameV0 | Kind::MultiFrameV0, _) => (Failure, Ignore(ProcessError::UnsupportedVersion)),
                 }
             }
         };
@@ -140,6 +191,10 @@ impl<const MTU: usize> TransferMachine<MTU>
         output
     }
 
+    pub(crate) fn is_assembling(&self) -> bool {
+        self.state == State::AssemblingT0 || self.state == State::AssemblingT1
+    }
+
     pub(crate) fn fail(&mut self) {
         self.state = State::Failure;
     }
@@ -158,33 +213,151 @@ impl<const MTU: usize> TransferMachine<MTU>
 
 #[derive(Copy, Clone)]
 pub(crate) struct Transfer<const MTU: usize> {
-    pub(crate) transfer_machine: TransferMachine<MTU>,
+    pub(crate) transfer_machine: TransferMachine,
     pub(crate) first_piece_idx: Option<PieceIdx>,
     pub(crate) last_piece_idx: Option<PieceIdx>,
     pub(crate) last_piece_len: PieceByteIdx,
+    /// Copy of the last accepted frame with tail byte, kept even if its data wasn't stored.
+    /// Only tracked when duplicate frames are ignored.
+    pub(crate) last_frame: [u8; MTU],
+    /// Length of last_frame, 0 if there is none
+    pub(crate) last_frame_len: usize,
+    /// Amount of data bytes stored so far, including crc and padding
+    pub(crate) received_len: usize,
+    /// Amount of data bytes received so far, including the ones not stored because of truncation
+    pub(crate) total_len: usize,
+    /// Maximum payload length of the subscription, data beyond it is not stored
+    pub(crate) extent: usize,
+    /// Data beyond extent was received and discarded
+    pub(crate) truncated: bool,
+    /// Running CRC of all the frames received so far
+    pub(crate) crc: TransferCrc,
+    /// Amount of frames received so far, including the ones not stored because of truncation
+    pub(crate) frame_count: usize,
+    /// Redundant interface the first frame of the current transfer was received on
+    pub(crate) iface: u8,
     pub(crate) priority: Priority,
-    pub(crate) sequence_number: TransferSeq,
+    /// Timer ticks, same for all the other timestamps
     pub(crate) last_changed_timestamp: u32,
+    /// Time of arrival of the first frame of the current transfer
+    pub(crate) transfer_timestamp: u32,
+    /// Transfer ID of the last successfully received transfer and its first frame arrival time
+    pub(crate) last_transfer: Option<(TransferId, u32)>,
+    #[cfg(feature = "session-stats")]
+    pub(crate) stats: RawSessionStats,
 }
 impl<const MTU: usize> Transfer<MTU>
 {
-    pub(crate) fn new(priority: Priority, sequence_number: TransferSeq, time_now: u32) -> Self {
+    pub(crate) fn new(priority: Priority, extent: usize, time_now: u32) -> Self {
         Transfer {
             transfer_machine: TransferMachine::reset(),
             first_piece_idx: None,
             last_piece_idx: None,
             last_piece_len: 0,
+            last_frame: [0u8; MTU],
+            last_frame_len: 0,
+            received_len: 0,
+            total_len: 0,
+            extent,
+            truncated: false,
+            crc: TransferCrc::new(),
+            frame_count: 0,
+            iface: 0,
             priority,
-            sequence_number,
             last_changed_timestamp: time_now,
+            transfer_timestamp: time_now,
+            last_transfer: None,
+            #[cfg(feature = "session-stats")]
+            stats: RawSessionStats::default(),
+        }
+    }
+
+    /// Returns true if transfer with the same ID was already received from this session
+    /// less than transfer_id_timeout ago.
+    pub(crate) fn is_duplicate(&self, transfer_id: TransferId, time_now: u32, transfer_id_timeout: u32) -> bool {
+        match self.last_transfer {
+            Some((id, timestamp)) => {
+                id == transfer_id && elapsed(time_now, timestamp) <= transfer_id_timeout
+            }
+            None => false
+        }
+    }
+
+    /// Returns true if frame is the same as the one accepted last (retransmitted after lost ACK)
+    pub(crate) fn is_duplicate_frame(&self, priority: Priority, frame: &[u8]) -> bool {
+        self.last_frame_len != 0 &&
+            priority == self.priority &&
+            frame == &self.last_frame[..self.last_frame_len]
+    }
+
+    /// Remember the frame just accepted, see is_duplicate_frame(). Frame must not be longer than MTU.
+    pub(crate) fn set_last_frame(&mut self, frame: &[u8]) {
+        self.last_frame[..frame.len()].copy_from_slice(frame);
+        self.last_frame_len = frame.len();
+    }
+
+    /// Remove all pieces of the current transfer from storage and return an amount of pieces removed
+    pub(crate) fn remove_pieces<const N: usize, const MAX_PIECES: usize>(
+        &mut self,
+        storage: &mut PiecesStorage<N, MAX_PIECES>
+    ) -> PieceIdx {
+        let removed = self.first_piece_idx.map(|idx| storage.remove_all(idx)).unwrap_or(0);
+        self.forget_pieces();
+        self.last_frame_len = 0;
+        removed
+    }
+
+    /// Reset assembly progress, pieces must be already removed from storage or handed over
+    pub(crate) fn forget_pieces(&mut self) {
+        self.first_piece_idx = None;
+        self.last_piece_idx = None;
+        self.received_len = 0;
+        self.total_len = 0;
+        self.truncated = false;
+        self.crc = TransferCrc::new();
+        self.frame_count = 0;
+    }
+
+    /// Hand over pieces of a just completed transfer to the ready queue, so that session can receive
+    /// next transfers right away.
+    pub(crate) fn take_completed(&mut self, key: TransfersMapKey, sequence_number: TransferSeq) -> Option<QueuedTransfer> {
+        if self.transfer_machine.state != State::Done {
+            return None;
         }
+        let first_piece_idx = self.first_piece_idx?;
+        let is_multi_frame = Some(first_piece_idx) != self.last_piece_idx;
+        let (payload_len, piece_len) = if is_multi_frame || self.truncated {
+            // Multi frame transfers end with crc, padding (if any) is left in place.
+            // When truncated, crc or a part of it might be stored as well.
+            let payload_len = core::cmp::min(self.received_len, self.total_len - 2);
+            (payload_len, self.transfer_machine.mtu - 1)
+        } else {
+            (self.received_len, self.last_piece_len)
+        };
+        // Implicit truncation
+        let payload_len = core::cmp::min(payload_len, self.extent);
+        let transfer_id = self.transfer_machine.transfer_id?;
+        let frame_count = self.frame_count;
+        self.forget_pieces();
+        Some(QueuedTransfer {
+            key,
+            priority: self.priority,
+            transfer_id,
+            first_frame_timestamp: self.transfer_timestamp,
+            last_frame_timestamp: self.last_changed_timestamp,
+            frame_count,
+            iface: self.iface,
+            sequence_number,
+            first_piece_idx,
+            piece_len,
+            payload_len,
+        })
     }
 }
 impl<const MTU: usize> core::fmt::Display for Transfer<MTU> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        write!(f, "{:?} Seq:{} t:{} {} {:?}..={:?}/{}",
+        write!(f, "{:?} t:{} {} {:?}..={:?}/{}",
                self.priority,
-               self.sequence_number,
                self.last_changed_timestamp,
                self.transfer_machine,
                self.first_piece_idx,
@@ -194,6 +367,36 @@ impl<const MTU: usize> core::fmt::Display for Transfer<MTU> {
     }
 }
 
+/// Completed transfer waiting to be popped, owns its pieces in storage
+#[derive(Copy, Clone)]
+pub(crate) struct QueuedTransfer {
+    pub(crate) key: TransfersMapKey,
+    pub(crate) priority: Priority,
+    pub(crate) transfer_id: TransferId,
+    pub(crate) first_frame_timestamp: u32,
+    pub(crate) last_frame_timestamp: u32,
+    pub(crate) frame_count: usize,
+    pub(crate) iface: u8,
+    pub(crate) sequence_number: TransferSeq,
+    pub(crate) first_piece_idx: PieceIdx,
+    /// Amount of data bytes in each piece, last one might be used partially
+    pub(crate) piece_len: PieceByteIdx,
+    pub(crate) payload_len: usize,
+}
+impl core::fmt::Display for QueuedTransfer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} {} {:?} Seq:{} {}../{} len:{}",
+               self.key.source,
+               self.key.kind,
+               self.priority,
+               self.sequence_number,
+               self.first_piece_idx,
+               self.piece_len,
+               self.payload_len,
+        )
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Hash32)]
 pub(crate) struct TransfersMapKey {
     pub(crate) kind: TransferKind,
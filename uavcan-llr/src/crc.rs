@@ -0,0 +1,66 @@
+/// CRC-16/CCITT-FALSE that can be updated one frame at a time and stored alongside each transfer.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) struct TransferCrc(u16);
+
+const POLY: u16 = 0x1021;
+
+const fn make_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ POLY } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u16; 256] = make_table();
+
+impl TransferCrc {
+    pub(crate) const fn new() -> Self {
+        TransferCrc(0xFFFF)
+    }
+
+    pub(crate) fn add(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = (self.0 << 8) ^ TABLE[((self.0 >> 8) as u8 ^ b) as usize];
+        }
+    }
+
+    pub(crate) fn get(&self) -> u16 {
+        self.0
+    }
+
+    /// Data followed by its own CRC (big endian) leaves zero in the register.
+    pub(crate) fn is_residue_zero(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::crc::TransferCrc;
+
+    #[test]
+    fn check_crc() {
+        let mut crc = TransferCrc::new();
+        crc.add(b"1234");
+        crc.add(b"56789");
+        assert_eq!(crc, TransferCrc(0x29B1));
+        crc.add(&[0x29, 0xB1]);
+        assert!(crc.is_residue_zero());
+
+        let mut reference = crc_any::CRCu16::crc16ccitt_false();
+        reference.digest(&[0xaa; 100]);
+        let mut crc = TransferCrc::new();
+        crc.add(&[0xaa; 100]);
+        assert_eq!(crc, TransferCrc(reference.get_crc()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,37 @@
+/// Frame lengths that can be encoded with a CAN / CAN FD DLC, in ascending order.
+pub const FRAME_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Returns true if a frame carrying exactly `len` data bytes can be transmitted.
+pub fn is_valid_frame_len(len: usize) -> bool {
+    FRAME_LENGTHS.binary_search(&len).is_ok()
+}
+
+/// Smallest valid frame length that can hold `len` bytes, None if `len` exceeds 64 bytes.
+pub fn padded_frame_len(len: usize) -> Option<usize> {
+    FRAME_LENGTHS.iter().find(|&&l| l >= len).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::dlc::{is_valid_frame_len, padded_frame_len};
+
+    #[test]
+    fn check_frame_len() {
+        for len in 0..=8 {
+            assert!(is_valid_frame_len(len));
+            assert_eq!(padded_frame_len(len), Some(len));
+        }
+        assert!(!is_valid_frame_len(9));
+        assert!(!is_valid_frame_len(63));
+        assert!(is_valid_frame_len(64));
+        assert!(!is_valid_frame_len(65));
+
+        assert_eq!(padded_frame_len(9), Some(12));
+        assert_eq!(padded_frame_len(12), Some(12));
+        assert_eq!(padded_frame_len(25), Some(32));
+        assert_eq!(padded_frame_len(33), Some(48));
+        assert_eq!(padded_frame_len(64), Some(64));
+        assert_eq!(padded_frame_len(65), None);
+    }
+}
@@ -4,12 +4,27 @@
 pub mod types;
 pub mod slicer;
 pub mod tailbyte;
+pub mod dlc;
+mod crc;
 pub mod assembler;
+pub mod txqueue;
+pub mod tid_allocator;
+
+/// Time types used in the public API
+pub use fugit;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Error {
     WrongReservedBit,
     NoneZeroHighBits,
+    TooManySubscriptions,
+    /// TransferIdAllocator has no space left for a new session
+    TooManySessions,
+    BufferTooSmall,
+    /// Anonymous transfers must fit into a single frame
+    AnonymousPayloadTooLong,
+    /// Not enough space left in TxQueue for all frames of a transfer
+    TxQueueFull,
     #[cfg(feature = "vhrdcan")]
     StandardIdNotSupported,
 }
@@ -2,13 +2,16 @@
 
 use crate::types::TransferId;
 use core::slice::Chunks;
-use crate::tailbyte::TailByteIter;
+use crate::tailbyte::{TailByte, TailByteIter, Kind};
+use crate::crc::TransferCrc;
 use core::ops::Deref;
+use crate::Error;
 
 pub struct Slicer<'a, const MTU: usize, const MTU_M1: usize> {
     chunks: Chunks<'a, u8>,
     crc: [u8; 2],
     crc_bytes_left: u8, // = 2, 1 or 0
+    padding: usize, // zero bytes inserted before crc (or tail byte) of the last frame to make CAN FD DLC valid
     tail_bytes: TailByteIter,
 }
 
@@ -16,44 +19,60 @@ impl<'a, 'b, const MTU: usize, const MTU_M1: usize> Slicer<'a, MTU, MTU_M1> {
     pub fn new(payload: &'a[u8], transfer_id: TransferId) -> Slicer<'a, MTU, MTU_M1> {
         let max_chunk_len = MTU_M1;
         let chunks = payload.chunks(max_chunk_len);
+        let frame_count = frame_count::<MTU>(payload.len(), MTU);
         let mut crc = [0, 0];
-        let crc_bytes_left = if payload.len() <= 7 { // single frame transfers are protected by CAN Bus CRC
-            0
+        let (crc_bytes_left, padding) = if frame_count == 1 { // single frame transfers are protected by CAN Bus CRC
+            (0, last_frame_padding(payload.len() + 1))
         } else {
-            let mut crc16 = crc_any::CRCu16::crc16ccitt_false();
-            crc16.digest(payload);
-            let crc16 = crc16.get_crc().to_be_bytes();
-            crc.copy_from_slice(&crc16);
-            2
+            // last frame holds the rest of the payload, crc (or its last byte) and a tail byte
+            let last_frame_len = payload.len() + 2 - (frame_count - 1) * MTU_M1 + 1;
+            let padding = last_frame_padding(last_frame_len);
+            let mut crc16 = TransferCrc::new();
+            crc16.add(payload);
+            crc16.add(&[0u8; MTU][..padding]);
+            crc.copy_from_slice(&crc16.get().to_be_bytes());
+            (2, padding)
         };
         let tail_bytes = crate::tailbyte::TailByte::new_multi_frame(
             transfer_id,
-            frame_count::<MTU>(payload.len(), MTU)
+            frame_count
         );
 
         Slicer {
             chunks,
             crc,
             crc_bytes_left,
+            padding,
             tail_bytes,
         }
     }
 
+    /// Anonymous transfers can only be single frame, so payload must fit into MTU - 1 bytes.
+    /// Use CanId::new_anonymous_message_kind() for the frames.
+    pub fn new_anonymous(payload: &'a[u8], transfer_id: TransferId) -> Result<Slicer<'a, MTU, MTU_M1>, Error> {
+        if payload.len() > MTU_M1 {
+            return Err(Error::AnonymousPayloadTooLong);
+        }
+        Ok(Slicer::new(payload, transfer_id))
+    }
+
     #[cfg(feature = "vhrdcan")]
     pub fn new_single(payload: OwnedSlice<MTU_M1>, can_id: crate::types::CanId, transfer_id: &mut TransferId) -> vhrdcan::Frame<MTU> {
         let tail_byte = crate::tailbyte::TailByte::new_single_frame(*transfer_id);
         transfer_id.increment();
         let mut frame_bytes = [0u8; MTU];
         frame_bytes[0..payload.used].copy_from_slice(&payload);
-        frame_bytes[payload.used] = tail_byte.as_byte();
+        let frame_len = payload.used + 1 + last_frame_padding(payload.used + 1);
+        frame_bytes[frame_len - 1] = tail_byte.as_byte();
         unsafe {
-            vhrdcan::Frame::new_unchecked(can_id.into(), &frame_bytes[0..payload.used + 1])
+            vhrdcan::Frame::new_unchecked(can_id.into(), &frame_bytes[0..frame_len])
         }
     }
 
     pub fn frames_ref(self) -> RefSlicer<'a, MTU, MTU_M1> {
         RefSlicer {
-            slicer: self
+            slicer: self,
+            padding: 0,
         }
     }
 
@@ -73,60 +92,174 @@ pub fn frame_count<const MTU: usize>(payload_len: usize, mtu: usize) -> usize {
     }
 }
 
+/// Amount of zero bytes needed to extend last frame of `frame_len` bytes to the nearest valid CAN FD length.
+/// Always 0 for Classic CAN frames.
+fn last_frame_padding(frame_len: usize) -> usize {
+    crate::dlc::padded_frame_len(frame_len).unwrap_or(frame_len) - frame_len
+}
+
 pub struct RefSlicer<'a, const MTU: usize, const MTU_M1: usize> {
-    slicer: Slicer<'a, MTU, MTU_M1>
+    slicer: Slicer<'a, MTU, MTU_M1>,
+    padding: usize,
+}
+
+impl<'a, const MTU: usize, const MTU_M1: usize> RefSlicer<'a, MTU, MTU_M1> {
+    /// Amount of zero bytes to be inserted between the payload chunk and the tail of the frame returned
+    /// last by next(). Only the last frame of a CAN FD transfer is padded, 0 for all the other frames.
+    pub fn padding(&self) -> usize {
+        self.padding
+    }
 }
 
 impl<'a, const MTU: usize, const MTU_M1: usize> Iterator for RefSlicer<'a, MTU, MTU_M1> {
+    /// (payload chunk, crc bytes if any and a tail byte), see padding()
     type Item = (&'a [u8], OwnedSlice<3>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.slicer.tail_bytes.next() {
-            Some(tail_byte) => {
-                let tail_byte = tail_byte.as_byte();
-                match self.slicer.chunks.next() {
-                    Some(chunk) => {
-                        if chunk.len() <= MTU_M1 - 2 { // mtu8: len() <= 5 (2 byte crc and tail byte will fit)
-                            if self.slicer.crc_bytes_left == 2 {
-                                self.slicer.crc_bytes_left -= 2;
-                                Some((chunk, OwnedSlice::new_three(self.slicer.crc[0], self.slicer.crc[1], tail_byte)))
-                            } else if self.slicer.crc_bytes_left == 1 {
-                                self.slicer.crc_bytes_left -= 1;
-                                Some((chunk, OwnedSlice::new_two(self.slicer.crc[1], tail_byte)))
-                            } else { // can only be 2 or 1 bytes of crc left for transmission
-                                unreachable!()
-                            }
-                        } else if chunk.len() == MTU_M1 - 1 { // mtu8: len() == 6 (1 byte of crc and tail byte will fit)
-                            if self.slicer.crc_bytes_left == 2 {
-                                self.slicer.crc_bytes_left -= 1;
-                                Some((chunk, OwnedSlice::new_two(self.slicer.crc[0], tail_byte)))
-                            } else if self.slicer.crc_bytes_left == 1 {
-                                self.slicer.crc_bytes_left -= 1;
-                                Some((chunk, OwnedSlice::new_two(self.slicer.crc[1], tail_byte)))
-                            } else { // can only be 2 or 1 bytes of crc left for transmission
-                                unreachable!()
-                            }
-                        } else if chunk.len() == MTU_M1 { // mtu8: len() == 7 (only tail byte will fit), single frame transfer or full frames from multi frame
-                            Some((chunk, OwnedSlice::new_one(tail_byte)))
-                        } else { // size should be <= max_chunk_len=mtu-1 since we chunk input payload that way
-                            unreachable!()
-                        }
-                    },
-                    None => {
-                        if self.slicer.crc_bytes_left == 2 {
-                            self.slicer.crc_bytes_left -= 2;
-                            Some((&[], OwnedSlice::new_three(self.slicer.crc[0], self.slicer.crc[1], tail_byte)))
-                        } else if self.slicer.crc_bytes_left == 1 {
-                            self.slicer.crc_bytes_left -= 1;
-                            Some((&[], OwnedSlice::new_two(self.slicer.crc[1], tail_byte)))
-                        } else { // can only be 2 or 1 bytes of crc left for transmission
-                            unreachable!()
-                        }
-                    }
+        let tail_byte = self.slicer.tail_bytes.next()?; // None when all frames were consumed
+        // chunks are exhausted when last frame carries only crc (or its last byte)
+        let chunk = self.slicer.chunks.next().unwrap_or(&[]);
+        let is_last = tail_byte.kind == Kind::SingleFrame || tail_byte.is_multi_frame_end();
+        self.padding = if is_last { self.slicer.padding } else { 0 };
+        let tail = frame_tail::<MTU_M1>(chunk.len() + self.padding, tail_byte, self.slicer.crc, &mut self.slicer.crc_bytes_left);
+        Some((chunk, tail))
+    }
+}
+
+/// Crc bytes (if any are left and fit) and a tail byte that follow `used` bytes of payload and padding.
+fn frame_tail<const MTU_M1: usize>(used: usize, tail_byte: TailByte, crc: [u8; 2], crc_bytes_left: &mut u8) -> OwnedSlice<3> {
+    // mtu8: used == 7 - only tail byte will fit, 6 - 1 byte of crc and tail byte, <= 5 - 2 bytes of crc and tail byte
+    let crc_room = MTU_M1 - used;
+    let crc_bytes = core::cmp::min(crc_room, *crc_bytes_left as usize);
+    let tail_byte = tail_byte.as_byte();
+    let tail = match (crc_bytes, *crc_bytes_left) {
+        (0, _) => OwnedSlice::new_one(tail_byte),
+        (1, 2) => OwnedSlice::new_two(crc[0], tail_byte),
+        (1, _) => OwnedSlice::new_two(crc[1], tail_byte),
+        _ => OwnedSlice::new_three(crc[0], crc[1], tail_byte),
+    };
+    *crc_bytes_left -= crc_bytes as u8;
+    tail
+}
+
+/// Payload bytes of several segments following each other, see IterSlicer::new_gather().
+#[derive(Clone)]
+pub struct GatherBytes<'s, 'a> {
+    segments: core::slice::Iter<'s, &'a [u8]>,
+    current: core::slice::Iter<'a, u8>,
+}
+
+impl<'s, 'a> Iterator for GatherBytes<'s, 'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(&byte) = self.current.next() {
+                return Some(byte);
+            }
+            self.current = self.segments.next()?.iter();
+        }
+    }
+}
+
+/// Slicer over a payload that is not in one contiguous buffer, e.g. a header and a body in different places.
+/// Payload bytes are taken from any iterator (chained or flattened slices, a serializer, etc) and frames are
+/// exactly the same as produced by Slicer. CRC is computed along the way, so the payload is only iterated once.
+///
+/// If the source yields less than payload_len bytes, frames stop at the point where it ran out. If it yields
+/// more, last frame is not produced. In both cases receivers never get a complete transfer and
+/// is_length_mismatch() returns true.
+pub struct IterSlicer<I, const MTU: usize, const MTU_M1: usize> {
+    bytes: I,
+    payload_left: usize,
+    is_multi_frame: bool,
+    running_crc: TransferCrc,
+    crc: [u8; 2],
+    crc_bytes_left: u8, // = 2, 1 or 0, set after the last payload byte is consumed
+    padding: usize,
+    tail_bytes: TailByteIter,
+    length_mismatch: bool,
+}
+
+impl<I: Iterator<Item = u8>, const MTU: usize, const MTU_M1: usize> IterSlicer<I, MTU, MTU_M1> {
+    /// `bytes` must yield exactly `payload_len` bytes.
+    pub fn new(bytes: I, payload_len: usize, transfer_id: TransferId) -> IterSlicer<I, MTU, MTU_M1> {
+        let frame_count = frame_count::<MTU>(payload_len, MTU);
+        let is_multi_frame = frame_count > 1;
+        let padding = if is_multi_frame {
+            last_frame_padding(payload_len + 2 - (frame_count - 1) * MTU_M1 + 1)
+        } else {
+            last_frame_padding(payload_len + 1)
+        };
+        IterSlicer {
+            bytes,
+            payload_left: payload_len,
+            is_multi_frame,
+            running_crc: TransferCrc::new(),
+            crc: [0, 0],
+            crc_bytes_left: 0,
+            padding,
+            tail_bytes: crate::tailbyte::TailByte::new_multi_frame(transfer_id, frame_count),
+            length_mismatch: false,
+        }
+    }
+
+    /// Source yielded a different amount of bytes than payload_len, frames were cut short.
+    pub fn is_length_mismatch(&self) -> bool {
+        self.length_mismatch
+    }
+}
+
+impl<'s, 'a, const MTU: usize, const MTU_M1: usize> IterSlicer<GatherBytes<'s, 'a>, MTU, MTU_M1> {
+    /// Payload is the concatenation of all the segments.
+    pub fn new_gather(segments: &'s [&'a [u8]], transfer_id: TransferId) -> Self {
+        let payload_len = segments.iter().map(|segment| segment.len()).sum();
+        let bytes = GatherBytes {
+            segments: segments.iter(),
+            current: [].iter(),
+        };
+        IterSlicer::new(bytes, payload_len, transfer_id)
+    }
+}
+
+impl<I: Iterator<Item = u8>, const MTU: usize, const MTU_M1: usize> Iterator for IterSlicer<I, MTU, MTU_M1> {
+    type Item = OwnedSlice<MTU>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.length_mismatch {
+            return None;
+        }
+        let tail_byte = self.tail_bytes.next()?;
+        let is_last = tail_byte.kind == Kind::SingleFrame || tail_byte.is_multi_frame_end();
+        let chunk_len = core::cmp::min(self.payload_left, MTU_M1);
+        let padding = if is_last { self.padding } else { 0 };
+        let mut frame = [0u8; MTU];
+        for byte in frame[0..chunk_len].iter_mut() {
+            match self.bytes.next() {
+                Some(b) => *byte = b,
+                None => {
+                    self.length_mismatch = true;
+                    return None;
                 }
-            },
-            None => None // all frames were consumed
+            }
         }
+        self.payload_left -= chunk_len;
+        if is_last && self.bytes.next().is_some() {
+            self.length_mismatch = true;
+            return None;
+        }
+        if self.is_multi_frame && chunk_len != 0 {
+            // padding is covered by crc as well
+            self.running_crc.add(&frame[0..chunk_len + padding]);
+            if self.payload_left == 0 {
+                self.crc = self.running_crc.get().to_be_bytes();
+                self.crc_bytes_left = 2;
+            }
+        }
+        let tail_start = chunk_len + padding;
+        let tail = frame_tail::<MTU_M1>(tail_start, tail_byte, self.crc, &mut self.crc_bytes_left);
+        frame[tail_start..tail_start + tail.len()].copy_from_slice(&tail);
+        Some(OwnedSlice::new(frame, tail_start + tail.len()))
     }
 }
 
@@ -141,8 +274,9 @@ impl<'a, const MTU: usize, const MTU_M1: usize> Iterator for OwnedSlicer<'a, MTU
             Some((a, b)) => {
                 let mut frame = [0u8; MTU];
                 frame[0..a.len()].copy_from_slice(a);
-                frame[a.len()..a.len() + b.len()].copy_from_slice(&b);
-                Some(OwnedSlice::new(frame, a.len() + b.len()))
+                let b_start = a.len() + self.slicer.padding();
+                frame[b_start..b_start + b.len()].copy_from_slice(&b);
+                Some(OwnedSlice::new(frame, b_start + b.len()))
             },
             None => None
         }
@@ -268,7 +402,7 @@ impl<const N: usize> Deref for OwnedSlice<N> {
 mod tests {
     extern crate std;
     use crate::types::*;
-    use crate::slicer::{Slicer, OwnedSlice, frame_count};
+    use crate::slicer::{Slicer, IterSlicer, OwnedSlice, frame_count};
 
     #[test]
     fn check_frame_count() {
@@ -287,6 +421,11 @@ mod tests {
         assert_eq!(frame_count::<8>(20, 8), 4); // 7+t 7+t 6+cr+t c+t
         assert_eq!(frame_count::<8>(21, 8), 4); // 7+t 7+t 7+t crc+t
         assert_eq!(frame_count::<8>(26, 8), 4); // 7+t 7+t 7+t 5+crc+t
+
+        assert_eq!(frame_count::<64>(63, 64), 1);
+        assert_eq!(frame_count::<64>(64, 64), 2);  // 63+t 1+crc+t
+        assert_eq!(frame_count::<64>(124, 64), 2); // 63+t 61+crc+t
+        assert_eq!(frame_count::<64>(125, 64), 3); // 63+t 62+cr+t c+t
     }
 
     #[test]
@@ -363,4 +502,101 @@ mod tests {
         }));
         assert_eq!(slicer.next(), None);
     }
+
+    #[test]
+    fn check_fd_slicer() {
+        let payload = [0xaa; 10];
+        let mut slicer = Slicer::<64, 63>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+        let frame = slicer.next().unwrap();
+        assert_eq!(frame.used, 12); // 10+t padded to 12
+        assert_eq!(&frame[..10], &payload);
+        assert_eq!(&frame[10..], &[0, 0b1110_0000]);
+        assert_eq!(slicer.next(), None);
+
+        let payload = [0x55; 100];
+        let mut slicer = Slicer::<64, 63>::new(&payload, TransferId::new(3).unwrap()).frames_owned();
+        let frame = slicer.next().unwrap();
+        assert_eq!(frame.used, 64);
+        assert_eq!(&frame[..63], &payload[..63]);
+        assert_eq!(frame[63], 0b1010_0011);
+        // 37+crc+t padded to 48 with zeros before crc
+        let frame = slicer.next().unwrap();
+        assert_eq!(frame.used, 48);
+        assert_eq!(&frame[..37], &payload[63..]);
+        assert_eq!(&frame[37..45], &[0; 8]);
+        let mut crc16 = crc_any::CRCu16::crc16ccitt_false();
+        crc16.digest(&payload);
+        crc16.digest(&[0; 8]);
+        assert_eq!(&frame[45..47], &crc16.get_crc().to_be_bytes());
+        assert_eq!(frame[47], 0b0100_0011);
+        assert_eq!(slicer.next(), None);
+
+        let mut slicer = Slicer::<64, 63>::new(&payload, TransferId::new(3).unwrap()).frames_ref();
+        let (chunk, tail) = slicer.next().unwrap();
+        assert_eq!((chunk.len(), slicer.padding(), tail.len()), (63, 0, 1));
+        let (chunk, tail) = slicer.next().unwrap();
+        assert_eq!((chunk.len(), slicer.padding(), tail.len()), (37, 8, 3));
+
+        // crc split across last two frames, no padding needed
+        let payload = [0x11; 125];
+        let slicer = Slicer::<64, 63>::new(&payload, TransferId::new(0).unwrap()).frames_owned();
+        let lengths: std::vec::Vec<usize> = slicer.map(|f| f.used).collect();
+        assert_eq!(lengths, [64, 64, 2]);
+    }
+
+    #[test]
+    fn check_short_single_frame() {
+        let mut slicer = Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(5).unwrap()).frames_owned();
+        assert_eq!(slicer.next(), Some(OwnedSlice::from_slice(&[1, 2, 3, 0b1110_0101]).unwrap()));
+        assert_eq!(slicer.next(), None);
+
+        let mut slicer = Slicer::<8, 7>::new(&[], TransferId::new(5).unwrap()).frames_owned();
+        assert_eq!(slicer.next(), Some(OwnedSlice::from_slice(&[0b1110_0101]).unwrap()));
+        assert_eq!(slicer.next(), None);
+    }
+
+    #[test]
+    fn check_gather_slicer() {
+        let payload: std::vec::Vec<u8> = (0..200u8).collect();
+        for len in 0..payload.len() {
+            let payload = &payload[..len];
+            for split in [0, len / 3, len] {
+                let segments = [&payload[..split], &[][..], &payload[split..]];
+                let expected: std::vec::Vec<_> = Slicer::<8, 7>::new(payload, TransferId::new(3).unwrap()).frames_owned().collect();
+                let frames: std::vec::Vec<_> = IterSlicer::<_, 8, 7>::new_gather(&segments, TransferId::new(3).unwrap()).collect();
+                assert_eq!(frames, expected, "len {} split {}", len, split);
+
+                let expected: std::vec::Vec<_> = Slicer::<64, 63>::new(payload, TransferId::new(3).unwrap()).frames_owned().collect();
+                let frames: std::vec::Vec<_> = IterSlicer::<_, 64, 63>::new_gather(&segments, TransferId::new(3).unwrap()).collect();
+                assert_eq!(frames, expected, "len {} split {}", len, split);
+            }
+        }
+
+        let payload = [0x55; 20];
+        let frames: std::vec::Vec<_> = IterSlicer::<_, 8, 7>::new(payload.iter().copied(), payload.len(), TransferId::new(0).unwrap()).collect();
+        let expected: std::vec::Vec<_> = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().collect();
+        assert_eq!(frames, expected);
+
+        // Sources without a known length
+        let (header, body) = ([1u8, 2, 3], [[0x55u8; 4]; 5]);
+        let payload: std::vec::Vec<u8> = header.iter().chain(body.iter().flatten()).copied().collect();
+        let bytes = header.iter().chain(body.iter().flatten()).copied();
+        let frames: std::vec::Vec<_> = IterSlicer::<_, 8, 7>::new(bytes, payload.len(), TransferId::new(0).unwrap()).collect();
+        let expected: std::vec::Vec<_> = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().collect();
+        assert_eq!(frames, expected);
+
+        // Source that yields less or more bytes than payload_len never produces the last frame
+        let mut short = IterSlicer::<_, 8, 7>::new(0..10u8, 20, TransferId::new(0).unwrap());
+        assert_eq!(short.by_ref().count(), 1);
+        assert!(short.is_length_mismatch());
+        let mut long = IterSlicer::<_, 8, 7>::new(0..30u8, 20, TransferId::new(0).unwrap());
+        assert_eq!(long.by_ref().count(), 3);
+        assert!(long.is_length_mismatch());
+        let mut long = IterSlicer::<_, 8, 7>::new(0..5u8, 3, TransferId::new(0).unwrap());
+        assert_eq!(long.next(), None);
+        assert!(long.is_length_mismatch());
+        let mut exact = IterSlicer::<_, 8, 7>::new(0..20u8, 20, TransferId::new(0).unwrap());
+        assert_eq!(exact.by_ref().count(), 4);
+        assert!(!exact.is_length_mismatch());
+    }
 }
\ No newline at end of file
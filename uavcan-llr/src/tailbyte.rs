@@ -68,6 +68,10 @@ impl TailByte {
         ((self.kind as u8) << 5) | self.id.inner()
     }
 
+    pub fn is_start(&self) -> bool {
+        matches!(self.kind, Kind::SingleFrame | Kind::MultiFrame)
+    }
+
     pub fn is_multi_frame_middle(&self) -> bool {
         match self.kind {
             Kind::MiddleT0 | Kind::MiddleT1 => true,
@@ -0,0 +1,128 @@
+use crate::types::{TransferKind, TransferId};
+use crate::Error;
+use heapless::FnvIndexMap;
+
+/// Transfer IDs of outgoing sessions: subjects this node publishes to and (service, destination)
+/// pairs it sends requests to. Sessions are keyed by TransferKind, source is always the local node.
+/// MAX_SESSIONS must be a power of two.
+pub struct TransferIdAllocator<const MAX_SESSIONS: usize> {
+    /// Transfer ID to be used for the next transfer of each session
+    next: FnvIndexMap<TransferKind, TransferId, MAX_SESSIONS>,
+}
+
+impl<const MAX_SESSIONS: usize> TransferIdAllocator<MAX_SESSIONS> {
+    pub fn new() -> Self {
+        TransferIdAllocator {
+            next: FnvIndexMap::new(),
+        }
+    }
+
+    /// Transfer ID for the next transfer of a session, starting from 0 for new sessions.
+    pub fn allocate(&mut self, kind: TransferKind) -> Result<TransferId, Error> {
+        let next = match self.next.get_mut(&kind) {
+            Some(next) => next,
+            None => {
+                self.next.insert(kind, TransferId::default()).map_err(|_| Error::TooManySessions)?;
+                match self.next.get_mut(&kind) {
+                    Some(next) => next,
+                    None => unreachable!(),
+                }
+            }
+        };
+        let transfer_id = *next;
+        next.increment();
+        Ok(transfer_id)
+    }
+
+    /// Transfer ID that was allocated last for a session, responses to a request carry the same ID.
+    pub fn current(&self, kind: TransferKind) -> Option<TransferId> {
+        self.next.get(&kind).and_then(|next| TransferId::new((next.inner() + 31) % 32))
+    }
+
+    /// Transfer ID that allocate() will return next for each known session, to be persisted across reboots.
+    pub fn iter(&self) -> impl Iterator<Item = (TransferKind, TransferId)> + '_ {
+        self.next.iter().map(|(kind, next)| (*kind, *next))
+    }
+
+    /// Continue a session from the persisted state, next allocate() will return `next`.
+    pub fn restore(&mut self, kind: TransferKind, next: TransferId) -> Result<(), Error> {
+        self.next.insert(kind, next).map_err(|_| Error::TooManySessions)?;
+        Ok(())
+    }
+
+    /// Forget a session, its transfer IDs start from 0 again if it is used later.
+    pub fn remove(&mut self, kind: TransferKind) -> bool {
+        self.next.remove(&kind).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.next.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next.is_empty()
+    }
+}
+
+impl<const MAX_SESSIONS: usize> Default for TransferIdAllocator<MAX_SESSIONS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::types::*;
+    use crate::tid_allocator::TransferIdAllocator;
+    use crate::Error;
+
+    fn subject(subject_id: u16) -> TransferKind {
+        CanId::new_message_kind(NodeId::new(1).unwrap(), SubjectId::new(subject_id).unwrap(), false, Priority::Nominal).transfer_kind
+    }
+
+    fn request(destination: u8) -> TransferKind {
+        CanId::new_service_kind(NodeId::new(1).unwrap(), NodeId::new(destination).unwrap(), ServiceId::new(430).unwrap(), true, Priority::Nominal).transfer_kind
+    }
+
+    #[test]
+    fn check_allocate() {
+        let mut allocator = TransferIdAllocator::<4>::new();
+        assert_eq!(allocator.current(subject(7)), None);
+        assert_eq!(allocator.allocate(subject(7)), Ok(TransferId::new(0).unwrap()));
+        assert_eq!(allocator.allocate(subject(7)), Ok(TransferId::new(1).unwrap()));
+        assert_eq!(allocator.current(subject(7)), Some(TransferId::new(1).unwrap()));
+        // Sessions are independent, including requests to different nodes
+        assert_eq!(allocator.allocate(subject(8)), Ok(TransferId::new(0).unwrap()));
+        assert_eq!(allocator.allocate(request(2)), Ok(TransferId::new(0).unwrap()));
+        assert_eq!(allocator.allocate(request(3)), Ok(TransferId::new(0).unwrap()));
+        assert_eq!(allocator.allocate(request(2)), Ok(TransferId::new(1).unwrap()));
+        assert_eq!(allocator.allocate(request(4)), Err(Error::TooManySessions));
+        assert!(allocator.remove(request(3)));
+        assert_eq!(allocator.allocate(request(4)), Ok(TransferId::new(0).unwrap()));
+
+        for _ in 2..31 {
+            allocator.allocate(subject(7)).unwrap();
+        }
+        assert_eq!(allocator.allocate(subject(7)), Ok(TransferId::new(31).unwrap()));
+        assert_eq!(allocator.allocate(subject(7)), Ok(TransferId::new(0).unwrap()));
+        assert_eq!(allocator.current(subject(7)), Some(TransferId::new(0).unwrap()));
+    }
+
+    #[test]
+    fn check_persist() {
+        let mut allocator = TransferIdAllocator::<4>::new();
+        allocator.allocate(subject(7)).unwrap();
+        allocator.allocate(subject(7)).unwrap();
+        allocator.allocate(request(2)).unwrap();
+        let saved: std::vec::Vec<_> = allocator.iter().collect();
+
+        let mut restored = TransferIdAllocator::<4>::new();
+        for (kind, next) in saved {
+            restored.restore(kind, next).unwrap();
+        }
+        assert_eq!(restored.allocate(subject(7)), Ok(TransferId::new(2).unwrap()));
+        assert_eq!(restored.allocate(request(2)), Ok(TransferId::new(1).unwrap()));
+        assert_eq!(restored.len(), 2);
+    }
+}
\ No newline at end of file
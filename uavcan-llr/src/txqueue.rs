@@ -0,0 +1,199 @@
+use crate::types::{CanId, TransferId, TransferKind};
+use crate::slicer::{Slicer, OwnedSlice, frame_count};
+use crate::Error;
+use fugit::TimerInstantU32;
+use heapless::Vec;
+
+/// Frame waiting for transmission
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct TxFrame<const MTU: usize, const TIMER_HZ: u32> {
+    pub id: CanId,
+    pub frame: OwnedSlice<MTU>,
+    /// Frame is dropped instead of being sent after this instant
+    pub deadline: TimerInstantU32<TIMER_HZ>,
+}
+
+/// Frames of outgoing transfers, popped in CAN arbitration order (lowest CAN ID first),
+/// frames with the same CAN ID are popped in the order they were pushed.
+/// CAPACITY is the amount of frames, not transfers.
+pub struct TxQueue<const MTU: usize, const MTU_M1: usize, const CAPACITY: usize, const TIMER_HZ: u32> {
+    /// In the order of pushing, next frame is found with a linear search
+    frames: Vec<TxFrame<MTU, TIMER_HZ>, CAPACITY>,
+    expired_frames: usize,
+}
+
+impl<const MTU: usize, const MTU_M1: usize, const CAPACITY: usize, const TIMER_HZ: u32> TxQueue<MTU, MTU_M1, CAPACITY, TIMER_HZ> {
+    pub const fn new() -> Self {
+        TxQueue {
+            frames: Vec::new(),
+            expired_frames: 0,
+        }
+    }
+
+    /// Slice the payload and enqueue all of its frames. Nothing is enqueued if they don't fit.
+    /// Returns the amount of frames enqueued.
+    pub fn push(&mut self, id: CanId, transfer_id: TransferId, payload: &[u8], deadline: TimerInstantU32<TIMER_HZ>) -> Result<usize, Error> {
+        let is_anonymous = match id.transfer_kind {
+            TransferKind::Message(message) => message.is_anonymous,
+            TransferKind::Service(_) => false,
+        };
+        let slicer = if is_anonymous {
+            Slicer::<MTU, MTU_M1>::new_anonymous(payload, transfer_id)?
+        } else {
+            Slicer::<MTU, MTU_M1>::new(payload, transfer_id)
+        };
+        let count = frame_count::<MTU>(payload.len(), MTU);
+        if self.free() < count {
+            return Err(Error::TxQueueFull);
+        }
+        for frame in slicer.frames_owned() {
+            // Will not fail because of the check above
+            let _ = self.frames.push(TxFrame { id, frame, deadline });
+        }
+        Ok(count)
+    }
+
+    /// Next frame to be sent, its deadline is not checked.
+    pub fn peek(&self) -> Option<&TxFrame<MTU, TIMER_HZ>> {
+        self.next_idx().map(|idx| &self.frames[idx])
+    }
+
+    /// Remove and return the next frame to be sent, frames past their deadline are dropped.
+    pub fn pop(&mut self, time_now: TimerInstantU32<TIMER_HZ>) -> Option<TxFrame<MTU, TIMER_HZ>> {
+        self.remove_expired(time_now);
+        let idx = self.next_idx()?;
+        Some(self.frames.remove(idx))
+    }
+
+    /// Drop all frames past their deadline, returns the amount of frames dropped.
+    pub fn remove_expired(&mut self, time_now: TimerInstantU32<TIMER_HZ>) -> usize {
+        let len_before = self.frames.len();
+        self.frames.retain(|frame| frame.deadline >= time_now);
+        let removed = len_before - self.frames.len();
+        self.expired_frames += removed;
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Amount of frames that can still be pushed
+    pub fn free(&self) -> usize {
+        CAPACITY - self.frames.len()
+    }
+
+    /// Total amount of frames dropped because of their deadline
+    pub fn expired_frames(&self) -> usize {
+        self.expired_frames
+    }
+
+    fn next_idx(&self) -> Option<usize> {
+        let mut next: Option<(usize, u32)> = None;
+        for (idx, frame) in self.frames.iter().enumerate() {
+            let raw_id: u32 = frame.id.into();
+            match next {
+                // Strictly lower ID only, so that earlier frames with the same ID go first
+                Some((_, next_raw_id)) if next_raw_id <= raw_id => {}
+                _ => next = Some((idx, raw_id)),
+            }
+        }
+        next.map(|(idx, _)| idx)
+    }
+}
+
+impl<const MTU: usize, const MTU_M1: usize, const CAPACITY: usize, const TIMER_HZ: u32> Default for TxQueue<MTU, MTU_M1, CAPACITY, TIMER_HZ> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::types::*;
+    use crate::txqueue::TxQueue;
+    use crate::slicer::Slicer;
+    use crate::Error;
+    use fugit::TimerInstantU32;
+
+    fn ms(t: u32) -> TimerInstantU32<1000> {
+        TimerInstantU32::from_ticks(t)
+    }
+
+    fn message_id(node_id: u8, priority: Priority) -> CanId {
+        CanId::new_message_kind(NodeId::new(node_id).unwrap(), SubjectId::new(7).unwrap(), false, priority)
+    }
+
+    #[test]
+    fn check_tx_order() {
+        let mut queue = TxQueue::<8, 7, 16, 1000>::new();
+        let low = message_id(1, Priority::Low);
+        let high = message_id(1, Priority::High);
+        let payload = [0xaa; 10];
+        assert_eq!(queue.push(low, TransferId::new(0).unwrap(), &payload, ms(100)), Ok(2));
+        assert_eq!(queue.push(low, TransferId::new(1).unwrap(), &[1, 2, 3], ms(100)), Ok(1));
+        assert_eq!(queue.push(high, TransferId::new(0).unwrap(), &payload, ms(100)), Ok(2));
+        assert_eq!(queue.len(), 5);
+
+        let high_frames: std::vec::Vec<_> = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned().collect();
+        let low_frames: std::vec::Vec<_> = Slicer::<8, 7>::new(&payload, TransferId::new(0).unwrap()).frames_owned()
+            .chain(Slicer::<8, 7>::new(&[1, 2, 3], TransferId::new(1).unwrap()).frames_owned())
+            .collect();
+        assert_eq!(queue.peek().map(|f| f.id), Some(high));
+        for frame in high_frames {
+            let popped = queue.pop(ms(0)).unwrap();
+            assert_eq!((popped.id, popped.frame), (high, frame));
+        }
+        for frame in low_frames {
+            let popped = queue.pop(ms(0)).unwrap();
+            assert_eq!((popped.id, popped.frame), (low, frame));
+        }
+        assert_eq!(queue.pop(ms(0)), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_tx_capacity() {
+        let mut queue = TxQueue::<8, 7, 4, 1000>::new();
+        let id = message_id(1, Priority::Nominal);
+        assert_eq!(queue.push(id, TransferId::new(0).unwrap(), &[0; 10], ms(100)), Ok(2));
+        // 3 frames do not fit, none are enqueued
+        assert_eq!(queue.push(id, TransferId::new(1).unwrap(), &[0; 14], ms(100)), Err(Error::TxQueueFull));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.push(id, TransferId::new(1).unwrap(), &[0; 12], ms(100)), Ok(2));
+        assert_eq!(queue.free(), 0);
+
+        let anonymous = CanId::new_anonymous_message_kind(SubjectId::new(7).unwrap(), Priority::Nominal, &[0; 10]);
+        let mut queue = TxQueue::<8, 7, 4, 1000>::new();
+        assert_eq!(queue.push(anonymous, TransferId::new(0).unwrap(), &[0; 10], ms(100)), Err(Error::AnonymousPayloadTooLong));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_tx_deadline() {
+        let mut queue = TxQueue::<8, 7, 16, 1000>::new();
+        let high = message_id(1, Priority::High);
+        let low = message_id(1, Priority::Low);
+        queue.push(high, TransferId::new(0).unwrap(), &[0; 10], ms(10)).unwrap();
+        queue.push(low, TransferId::new(0).unwrap(), &[1, 2, 3], ms(20)).unwrap();
+        assert_eq!(queue.pop(ms(10)).map(|f| f.id), Some(high));
+        // Rest of the high priority transfer is late
+        assert_eq!(queue.pop(ms(11)).map(|f| f.id), Some(low));
+        assert_eq!(queue.expired_frames(), 1);
+
+        queue.push(low, TransferId::new(1).unwrap(), &[1, 2, 3], ms(5)).unwrap();
+        assert_eq!(queue.remove_expired(ms(5)), 0);
+        assert_eq!(queue.remove_expired(ms(6)), 1);
+        assert_eq!(queue.pop(ms(6)), None);
+
+        // Deadline after timer overflow
+        queue.push(low, TransferId::new(2).unwrap(), &[1, 2, 3], ms(5)).unwrap();
+        assert_eq!(queue.remove_expired(ms(u32::MAX - 5)), 0);
+        assert!(queue.pop(ms(u32::MAX - 5)).is_some());
+    }
+}
\ No newline at end of file
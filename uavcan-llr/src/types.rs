@@ -79,6 +79,11 @@ impl CanId {
         }
     }
 
+    /// Anonymous message with a pseudo source node ID derived from the payload, see pseudo_node_id().
+    pub fn new_anonymous_message_kind(subject_id: SubjectId, priority: Priority, payload: &[u8]) -> Self {
+        CanId::new_message_kind(pseudo_node_id(payload), subject_id, true, priority)
+    }
+
     pub fn new_service_kind(source_node_id: NodeId, destination_node_id: NodeId, service_id: ServiceId, is_request: bool, priority: Priority) -> Self {
         CanId {
             source_node_id,
@@ -91,6 +96,14 @@ impl CanId {
         }
     }
 }
+/// Source node ID of an anonymous transfer, lower 7 bits of the payload CRC.
+/// Different payloads from different anonymous nodes are then unlikely to collide on the bus.
+pub fn pseudo_node_id(payload: &[u8]) -> NodeId {
+    let mut crc = crate::crc::TransferCrc::new();
+    crc.add(payload);
+    NodeId((crc.get() & 0x7f) as u8)
+}
+
 impl Display for CanId {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "{} {}->", self.source_node_id, self.priority).ok();
@@ -197,6 +210,14 @@ pub enum TransferKind {
     Service(Service)
 }
 impl TransferKind {
+    pub fn port_id(&self) -> PortId {
+        match self {
+            TransferKind::Message(message) => PortId::Subject(message.subject_id),
+            TransferKind::Service(service) if service.is_request => PortId::Request(service.service_id),
+            TransferKind::Service(service) => PortId::Response(service.service_id),
+        }
+    }
+
     // Used in assembler.rs alongside source id to form a key into key-transfer map.
     pub(crate) fn ser(&self) -> u32 {
         match self {
@@ -235,6 +256,14 @@ impl core::fmt::Display for TransferKind {
     }
 }
 
+/// Subject or service port, services are split into requests and responses.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PortId {
+    Subject(SubjectId),
+    Request(ServiceId),
+    Response(ServiceId),
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Message {
     pub subject_id: SubjectId,
@@ -332,6 +361,15 @@ mod tests {
         assert!(Priority::Low < Priority::High);
     }
 
+    #[test]
+    fn check_pseudo_node_id() {
+        // CRC-16/CCITT-FALSE of "123456789" is 0x29b1
+        assert_eq!(pseudo_node_id(b"123456789"), NodeId::new(0x31).unwrap());
+        let id = CanId::new_anonymous_message_kind(SubjectId::new(8).unwrap(), Priority::Nominal, b"123456789");
+        assert_eq!(id.source_node_id, NodeId::new(0x31).unwrap());
+        assert_eq!(id.transfer_kind, TransferKind::Message(Message { subject_id: SubjectId::new(8).unwrap(), is_anonymous: true }));
+    }
+
     #[test]
     fn check_transfer_id() {
         assert_eq!(CanId::try_from(0b111 << 29), Err(Error::NoneZeroHighBits));